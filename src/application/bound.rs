@@ -0,0 +1,168 @@
+use hashbrown::HashMap;
+use petgraph::stable_graph::NodeIndex;
+use std::collections::BinaryHeap;
+
+use crate::domain::{integral_abs_diff, Profile, RoadNetwork};
+
+use super::end::Reach;
+
+// Horizon buckets per query, and relaxation passes per bucket before giving up on it
+const BUCKETS: usize = 32;
+const MAX_PASSES: usize = 8;
+
+// Lower bound on the area a partial route still has to add. From every node
+// within reach it keeps the lowest and highest cumulative climb the route can
+// reach within growing horizons (interior edge vertices included), so the rest
+// of the route lies in a band around its current elevation; the bound is the
+// area between the remaining target profile and that band.
+pub struct AreaBound {
+    step: f64,
+    // Routes are at least this long, so the target up to here must be covered
+    end: f64,
+    // Per node, (min, max) climb within b * step for b = 0..=BUCKETS
+    bands: HashMap<NodeIndex, Vec<(f64, f64)>>,
+}
+
+struct Hop {
+    to: usize,
+    length: f64,
+    climb: f64,
+    profile: Vec<(f64, f64)>,
+}
+
+impl AreaBound {
+    // `origins` are nodes the search reaches first, with the length used to get
+    // there; only nodes within `horizon` of them get bands.
+    pub fn new<N: RoadNetwork + ?Sized>(data: &N, origins: &[(NodeIndex, f64)], horizon: f64, end: f64) -> Self {
+        let step = horizon / BUCKETS as f64;
+        let mut dist: HashMap<NodeIndex, f64> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        for &(n, d) in origins {
+            if d <= horizon && dist.get(&n).is_none_or(|&known| d < known) {
+                dist.insert(n, d);
+                heap.push(Reach(d, n));
+            }
+        }
+        while let Some(Reach(d, n)) = heap.pop() {
+            if dist.get(&n).is_some_and(|&known| d > known) {
+                continue;
+            }
+            for (e_idx, target) in data.out_edges(n) {
+                let nd = d + data.edge(e_idx).length;
+                if nd <= horizon && dist.get(&target).is_none_or(|&known| nd < known) {
+                    dist.insert(target, nd);
+                    heap.push(Reach(nd, target));
+                }
+            }
+        }
+
+        let nodes: Vec<NodeIndex> = dist.keys().copied().collect();
+        let dense: HashMap<NodeIndex, usize> = nodes.iter().enumerate().map(|(i, &n)| (n, i)).collect();
+        const OUTSIDE: usize = usize::MAX;
+        let hops: Vec<Vec<Hop>> = nodes
+            .iter()
+            .map(|&n| {
+                data.out_edges(n)
+                    .into_iter()
+                    .map(|(e_idx, target)| {
+                        let edge = data.edge(e_idx);
+                        Hop {
+                            to: dense.get(&target).copied().unwrap_or(OUTSIDE),
+                            length: edge.length,
+                            climb: edge.climb,
+                            profile: edge.profile_between(0.0, 1.0),
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let unbounded = (f64::NEG_INFINITY, f64::INFINITY);
+        let mut bands: Vec<Vec<(f64, f64)>> = vec![vec![(0.0, 0.0)]; nodes.len()];
+        for b in 1..=BUCKETS {
+            let h = b as f64 * step;
+            for band in bands.iter_mut() {
+                band.push(band[b - 1]);
+            }
+            // Edges shorter than a bucket lead back into this one; relax until stable
+            let mut settled = false;
+            for _ in 0..MAX_PASSES {
+                settled = true;
+                for (i, node_hops) in hops.iter().enumerate() {
+                    let (mut lo, mut hi) = bands[i][b];
+                    for hop in node_hops {
+                        let (edge_lo, edge_hi) = band_along(&hop.profile, h);
+                        lo = lo.min(edge_lo);
+                        hi = hi.max(edge_hi);
+                        if hop.length < h {
+                            let rest = ((h - hop.length) / step).ceil() as usize;
+                            let (next_lo, next_hi) = if hop.to == OUTSIDE { unbounded } else { bands[hop.to][rest.min(b)] };
+                            lo = lo.min(hop.climb + next_lo);
+                            hi = hi.max(hop.climb + next_hi);
+                        }
+                    }
+                    if (lo, hi) != bands[i][b] {
+                        bands[i][b] = (lo, hi);
+                        settled = false;
+                    }
+                }
+                if settled {
+                    break;
+                }
+            }
+            if !settled {
+                for band in bands.iter_mut() {
+                    band[b] = unbounded;
+                }
+            }
+        }
+        AreaBound { step, end, bands: nodes.into_iter().zip(bands).collect() }
+    }
+
+    // Area still to come for a route at node `n` after length `s`, at elevation
+    // `rel` relative to its start. Never more than the true remaining area.
+    pub fn remaining(&self, target: &Profile, n: NodeIndex, s: f64, rel: f64) -> f64 {
+        let Some(band) = self.bands.get(&n) else { return 0.0 };
+        if s >= self.end || self.step <= 0.0 {
+            return 0.0;
+        }
+        let mut knots = vec![s];
+        knots.extend(target.points.iter().map(|p| p.0).filter(|&k| k > s && k < self.end));
+        knots.extend((1..=BUCKETS).map(|b| s + b as f64 * self.step).filter(|&k| k < self.end));
+        knots.push(self.end);
+        knots.sort_by(f64::total_cmp);
+        knots
+            .windows(2)
+            .filter(|w| w[1] > w[0])
+            .map(|w| {
+                let b = ((w[1] - s) / self.step).ceil() as usize;
+                let Some(&(lo, hi)) = band.get(b) else { return 0.0 };
+                let (t0, t1) = (target.interpolate(w[0]), target.interpolate(w[1]));
+                let len = w[1] - w[0];
+                let below = if lo.is_finite() { positive_part(len, rel + lo - t0, rel + lo - t1) } else { 0.0 };
+                let above = if hi.is_finite() { positive_part(len, t0 - rel - hi, t1 - rel - hi) } else { 0.0 };
+                below + above
+            })
+            .sum()
+    }
+}
+
+// Lowest and highest point of an edge profile within distance `h` of its start
+fn band_along(profile: &[(f64, f64)], h: f64) -> (f64, f64) {
+    let (mut lo, mut hi) = (0.0f64, 0.0f64);
+    for w in profile.windows(2) {
+        let ((s0, z0), (s1, z1)) = (w[0], w[1]);
+        if s0 >= h {
+            break;
+        }
+        let z = if s1 > h { z0 + (z1 - z0) * (h - s0) / (s1 - s0) } else { z1 };
+        lo = lo.min(z);
+        hi = hi.max(z);
+    }
+    (lo, hi)
+}
+
+// Integral of max(f, 0) for f linear from `a` to `b` over `len`
+fn positive_part(len: f64, a: f64, b: f64) -> f64 {
+    (integral_abs_diff(len, a, b) + (a + b) / 2.0 * len) / 2.0
+}
@@ -0,0 +1,150 @@
+use petgraph::stable_graph::EdgeIndex;
+use std::fmt;
+
+use super::osm::unproject_web_mercator;
+use crate::domain::RoadGraph;
+
+#[derive(Clone, Copy, Debug)]
+pub struct CheckTolerances {
+    pub climb_m: f64,
+    pub slope: f64,
+    // Relative to the expected length, with a 1m floor so short edges are not flagged for rounding
+    pub length_rel: f64,
+}
+
+impl Default for CheckTolerances {
+    fn default() -> Self {
+        CheckTolerances { climb_m: 0.5, slope: 0.005, length_rel: 0.05 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckKind {
+    Climb,
+    Slope,
+    Length,
+}
+
+impl fmt::Display for CheckKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            CheckKind::Climb => "climb differs from elev(v) - elev(u)",
+            CheckKind::Slope => "slope differs from climb / length",
+            CheckKind::Length => "length differs from the distance between its endpoints",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CheckIssue {
+    pub edge_id: u64,
+    pub kind: CheckKind,
+    pub actual: f64,
+    pub expected: f64,
+}
+
+impl fmt::Display for CheckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "edge {}: {} ({:.3} vs {:.3})", self.edge_id, self.kind, self.actual, self.expected)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CheckReport {
+    pub edges_checked: usize,
+    pub issues: Vec<CheckIssue>,
+    pub edges_repaired: usize,
+}
+
+impl CheckReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn count(&self, kind: CheckKind) -> usize {
+        self.issues.iter().filter(|i| i.kind == kind).count()
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} edges checked, {} climb, {} slope and {} length outlier(s)",
+            self.edges_checked,
+            self.count(CheckKind::Climb),
+            self.count(CheckKind::Slope),
+            self.count(CheckKind::Length)
+        )?;
+        for issue in &self.issues {
+            writeln!(f, "  {}", issue)?;
+        }
+        if self.edges_repaired > 0 {
+            writeln!(f, "{} edge(s) repaired", self.edges_repaired)?;
+        }
+        Ok(())
+    }
+}
+
+// Length an edge should have given its endpoints (or polyline). Web Mercator
+// distances are scaled back to ground meters at the edge's latitude.
+fn expected_length(graph: &RoadGraph, e: EdgeIndex) -> f64 {
+    let (u, v) = graph.graph.edge_endpoints(e).unwrap();
+    let (a, b) = (&graph.graph[u], &graph.graph[v]);
+    let planar = match &graph.graph[e].geometry {
+        Some(geometry) => geometry.length_2d(),
+        None => ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt(),
+    };
+    if graph.meta.crs.as_deref() == Some("EPSG:3857") {
+        let (lat, _) = unproject_web_mercator((a.x + b.x) / 2.0, (a.y + b.y) / 2.0);
+        planar * lat.to_radians().cos()
+    } else {
+        planar
+    }
+}
+
+// Compare each edge's stored length, climb and slope with the values derived
+// from its endpoints. With `repair`, flagged fields are recomputed: length from
+// the endpoints, climb from the node elevations, and slope from both.
+pub fn check_consistency(graph: &mut RoadGraph, tolerances: &CheckTolerances, repair: bool) -> CheckReport {
+    let mut report = CheckReport::default();
+    let edges: Vec<EdgeIndex> = graph.graph.edge_indices().collect();
+    for e in edges {
+        let (u, v) = graph.graph.edge_endpoints(e).unwrap();
+        let expected_climb = graph.graph[v].elev - graph.graph[u].elev;
+        let expected_len = expected_length(graph, e);
+        let edge = &graph.graph[e];
+        report.edges_checked += 1;
+
+        let mut flagged = Vec::new();
+        if (edge.length - expected_len).abs() > tolerances.length_rel * expected_len.max(1.0) {
+            flagged.push((CheckKind::Length, edge.length, expected_len));
+        }
+        if (edge.climb - expected_climb).abs() > tolerances.climb_m {
+            flagged.push((CheckKind::Climb, edge.climb, expected_climb));
+        }
+        let expected_slope = if edge.length > 0.0 { edge.climb / edge.length } else { 0.0 };
+        if (edge.slope - expected_slope).abs() > tolerances.slope {
+            flagged.push((CheckKind::Slope, edge.slope, expected_slope));
+        }
+        if flagged.is_empty() {
+            continue;
+        }
+        let edge_id = edge.id;
+        if repair {
+            let edge = &mut graph.graph[e];
+            for &(kind, _, expected) in &flagged {
+                match kind {
+                    CheckKind::Length => edge.length = expected,
+                    CheckKind::Climb => edge.climb = expected,
+                    CheckKind::Slope => {}
+                }
+            }
+            edge.slope = if edge.length > 0.0 { edge.climb / edge.length } else { 0.0 };
+            report.edges_repaired += 1;
+        }
+        report.issues.extend(flagged.into_iter().map(|(kind, actual, expected)| CheckIssue { edge_id, kind, actual, expected }));
+    }
+    report
+}
@@ -0,0 +1,81 @@
+use anyhow::{anyhow, Result};
+use petgraph::stable_graph::EdgeIndex;
+
+use crate::domain::{Profile, RoadNetwork, Route};
+
+// The part of a graph edge a route covers, as fractions along that edge
+#[derive(Clone, Debug)]
+pub struct RouteLeg {
+    pub edge: EdgeIndex,
+    pub from: f64,
+    pub to: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct DecodedRoute {
+    pub legs: Vec<RouteLeg>,
+    pub geometry: Vec<[f64; 2]>,
+    pub profile: Profile,
+}
+
+impl DecodedRoute {
+    pub fn length(&self) -> f64 {
+        self.profile.total_length()
+    }
+}
+
+// Turn a route in source edge ids back into graph edges, its polyline and its
+// elevation profile. The first edge starts at `si` and the last ends at `ti`;
+// split source edges expand to the sub-edges covering that range.
+pub fn decode_route<N: RoadNetwork + ?Sized>(network: &N, route: &Route) -> Result<DecodedRoute> {
+    let mut legs: Vec<RouteLeg> = Vec::new();
+    let last = route.edge_ids.len().saturating_sub(1);
+    for (i, &id) in route.edge_ids.iter().enumerate() {
+        let chain = network.source_edges(id);
+        if chain.is_empty() {
+            return Err(anyhow!("Edge {} not found", id));
+        }
+        let start = if i == 0 { route.si } else { 0.0 };
+        let end = if i == last { route.ti } else { 1.0 };
+        for e in chain {
+            let (from, to) = network.edge(e).origin.map(|o| (o.from, o.to)).unwrap_or((0.0, 1.0));
+            let (lo, hi) = (start.max(from), end.min(to));
+            if hi <= lo {
+                continue;
+            }
+            if let Some(prev) = legs.last() {
+                if network.edge_endpoints(prev.edge).1 != network.edge_endpoints(e).0 {
+                    return Err(anyhow!("Route is not connected: edge {} does not continue edge {}", id, network.edge(prev.edge).source_id()));
+                }
+            }
+            legs.push(RouteLeg { edge: e, from: (lo - from) / (to - from), to: (hi - from) / (to - from) });
+        }
+    }
+
+    let mut geometry: Vec<[f64; 2]> = Vec::new();
+    let mut points = vec![(0.0, 0.0)];
+    let (mut s, mut rel) = (0.0, 0.0);
+    for leg in &legs {
+        let edge = network.edge(leg.edge);
+        let line = match &edge.geometry {
+            Some(g) => g.slice(leg.from, leg.to).points,
+            None => {
+                let (u, v) = network.edge_endpoints(leg.edge);
+                let (a, b) = (network.node(u), network.node(v));
+                let at = |t: f64| [a.x + t * (b.x - a.x), a.y + t * (b.y - a.y)];
+                vec![at(leg.from), at(leg.to)]
+            }
+        };
+        let skip = usize::from(geometry.last() == line.first());
+        geometry.extend_from_slice(&line[skip..]);
+
+        let shape = edge.profile_between(leg.from, leg.to);
+        for &(ds, dz) in &shape[1..] {
+            points.push((s + ds, rel + dz));
+        }
+        let (ds, dz) = shape[shape.len() - 1];
+        s += ds;
+        rel += dz;
+    }
+    Ok(DecodedRoute { legs, geometry, profile: Profile { points } })
+}
@@ -0,0 +1,403 @@
+use anyhow::{anyhow, Result};
+use hashbrown::HashSet;
+use petgraph::stable_graph::NodeIndex;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use tiff::decoder::{Decoder, DecodingResult, Limits};
+use tiff::tags::Tag;
+
+use super::unproject_web_mercator;
+use crate::domain::{NodeData, RoadGraph};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DemFormat {
+    Hgt,
+    AsciiGrid,
+    GeoTiff,
+}
+
+// Coordinate system of the raster grid
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DemCrs {
+    // Longitude/latitude in degrees (WGS84)
+    Geographic,
+    // Same projected coordinates as the graph nodes
+    Graph,
+}
+
+// A single-band elevation raster. Cell (row, col) is centered at
+// (x0 + col * dx, y0 - row * dy); row 0 is the northern edge.
+#[derive(Clone, Debug)]
+pub struct Dem {
+    pub ncols: usize,
+    pub nrows: usize,
+    pub x0: f64,
+    pub y0: f64,
+    pub dx: f64,
+    pub dy: f64,
+    pub values: Vec<f32>,
+    pub nodata: Option<f32>,
+    pub crs: DemCrs,
+}
+
+impl Dem {
+    fn value(&self, row: usize, col: usize) -> Option<f64> {
+        let v = self.values[row * self.ncols + col];
+        if !v.is_finite() || Some(v) == self.nodata {
+            None
+        } else {
+            Some(v as f64)
+        }
+    }
+
+    // Bilinear interpolation in raster coordinates. Void cells are left out and
+    // the remaining weights renormalized; None outside the raster or if all four are void.
+    pub fn sample(&self, x: f64, y: f64) -> Option<f64> {
+        let (max_c, max_r) = ((self.ncols - 1) as f64, (self.nrows - 1) as f64);
+        let fc = (x - self.x0) / self.dx;
+        let fr = (self.y0 - y) / self.dy;
+        // Points in the outer half of the border cells take the edge value
+        if !(fc >= -0.5 && fr >= -0.5 && fc <= max_c + 0.5 && fr <= max_r + 0.5) {
+            return None;
+        }
+        let (fc, fr) = (fc.clamp(0.0, max_c), fr.clamp(0.0, max_r));
+        let c0 = (fc.floor() as usize).min(self.ncols.saturating_sub(2));
+        let r0 = (fr.floor() as usize).min(self.nrows.saturating_sub(2));
+        let (tc, tr) = (fc - c0 as f64, fr - r0 as f64);
+        let c1 = (c0 + 1).min(self.ncols - 1);
+        let r1 = (r0 + 1).min(self.nrows - 1);
+        let corners = [
+            (r0, c0, (1.0 - tr) * (1.0 - tc)),
+            (r0, c1, (1.0 - tr) * tc),
+            (r1, c0, tr * (1.0 - tc)),
+            (r1, c1, tr * tc),
+        ];
+        let (mut sum, mut weight) = (0.0, 0.0);
+        for (r, c, w) in corners {
+            if let Some(v) = self.value(r, c) {
+                sum += w * v;
+                weight += w;
+            }
+        }
+        if weight > 1e-12 {
+            Some(sum / weight)
+        } else {
+            // Only zero-weight corners are valid (sample sits on a void cell edge)
+            corners.iter().find_map(|&(r, c, _)| self.value(r, c))
+        }
+    }
+}
+
+pub fn detect_dem_format(path: &Path) -> Result<DemFormat> {
+    let ext = path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+    match ext.as_deref() {
+        Some("hgt") => Ok(DemFormat::Hgt),
+        Some("asc") | Some("grd") => Ok(DemFormat::AsciiGrid),
+        Some("tif") | Some("tiff") => Ok(DemFormat::GeoTiff),
+        _ => Err(anyhow!("Cannot tell DEM format of {:?} (expected .hgt, .asc or .tif)", path)),
+    }
+}
+
+// `ascii_crs` says how to read ESRI ASCII grids, which carry no CRS of their own.
+pub fn load_dem(path: &Path, ascii_crs: DemCrs) -> Result<Dem> {
+    match detect_dem_format(path)? {
+        DemFormat::Hgt => load_hgt(path),
+        DemFormat::AsciiGrid => load_ascii_grid(path, ascii_crs),
+        DemFormat::GeoTiff => load_geotiff(path),
+    }
+}
+
+// SRTM tile: square grid of big-endian i16, named after its south-west corner (N47E008.hgt)
+pub fn load_hgt(path: &Path) -> Result<Dem> {
+    let name = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| anyhow!("Invalid .hgt file name {:?}", path))?
+        .to_uppercase();
+    let parse_corner = |s: &str| -> Option<(f64, f64)> {
+        let lat_sign = match s.get(0..1)? {
+            "N" => 1.0,
+            "S" => -1.0,
+            _ => return None,
+        };
+        let lon_sign = match s.get(3..4)? {
+            "E" => 1.0,
+            "W" => -1.0,
+            _ => return None,
+        };
+        let lat: f64 = s.get(1..3)?.parse().ok()?;
+        let lon: f64 = s.get(4..7)?.parse().ok()?;
+        Some((lat_sign * lat, lon_sign * lon))
+    };
+    let (lat, lon) = parse_corner(&name).ok_or_else(|| anyhow!("Cannot read tile corner from {:?}", path))?;
+
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    let n = ((bytes.len() / 2) as f64).sqrt() as usize;
+    if n < 2 || n * n * 2 != bytes.len() {
+        return Err(anyhow!("{:?} is not a square SRTM tile ({} bytes)", path, bytes.len()));
+    }
+    let values = bytes.chunks_exact(2).map(|b| i16::from_be_bytes([b[0], b[1]]) as f32).collect();
+    let step = 1.0 / (n - 1) as f64;
+    Ok(Dem {
+        ncols: n,
+        nrows: n,
+        x0: lon,
+        y0: lat + 1.0,
+        dx: step,
+        dy: step,
+        values,
+        nodata: Some(-32768.0),
+        crs: DemCrs::Geographic,
+    })
+}
+
+pub fn load_ascii_grid(path: &Path, crs: DemCrs) -> Result<Dem> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let (mut ncols, mut nrows, mut cellsize) = (None, None, None);
+    let (mut xll, mut yll, mut centered) = (None, None, false);
+    let mut nodata = None;
+    let mut values: Vec<f32> = Vec::new();
+
+    for line in lines.by_ref() {
+        let line = line?;
+        let mut parts = line.split_whitespace();
+        let Some(key) = parts.next() else { continue };
+        if key.parse::<f64>().is_ok() {
+            // First data row
+            for v in line.split_whitespace() {
+                values.push(v.parse().map_err(|_| anyhow!("Invalid grid value {:?}", v))?);
+            }
+            break;
+        }
+        let value = parts.next().ok_or_else(|| anyhow!("ASCII grid header {:?} has no value", key))?;
+        let number = || value.parse::<f64>().map_err(|_| anyhow!("Invalid {} value {:?}", key, value));
+        match key.to_lowercase().as_str() {
+            "ncols" => ncols = Some(number()? as usize),
+            "nrows" => nrows = Some(number()? as usize),
+            "cellsize" => cellsize = Some(number()?),
+            "xllcorner" => xll = Some(number()?),
+            "yllcorner" => yll = Some(number()?),
+            "xllcenter" => {
+                xll = Some(number()?);
+                centered = true;
+            }
+            "yllcenter" => {
+                yll = Some(number()?);
+                centered = true;
+            }
+            "nodata_value" => nodata = Some(number()? as f32),
+            _ => return Err(anyhow!("Unknown ASCII grid header {:?}", key)),
+        }
+    }
+    for line in lines {
+        for v in line?.split_whitespace() {
+            values.push(v.parse().map_err(|_| anyhow!("Invalid grid value {:?}", v))?);
+        }
+    }
+
+    let missing = |name: &str| anyhow!("ASCII grid {:?} has no {} header", path, name);
+    let ncols = ncols.ok_or_else(|| missing("ncols"))?;
+    let nrows = nrows.ok_or_else(|| missing("nrows"))?;
+    let cellsize = cellsize.ok_or_else(|| missing("cellsize"))?;
+    let (xll, yll) = (xll.ok_or_else(|| missing("xllcorner"))?, yll.ok_or_else(|| missing("yllcorner"))?);
+    if values.len() != ncols * nrows {
+        return Err(anyhow!("ASCII grid {:?} has {} values, expected {}", path, values.len(), ncols * nrows));
+    }
+    let half = if centered { 0.0 } else { cellsize / 2.0 };
+    Ok(Dem {
+        ncols,
+        nrows,
+        x0: xll + half,
+        y0: yll + half + (nrows - 1) as f64 * cellsize,
+        dx: cellsize,
+        dy: cellsize,
+        values,
+        nodata,
+        crs,
+    })
+}
+
+// Single-band GeoTIFF georeferenced by a tie point and pixel scale (no rotation)
+pub fn load_geotiff(path: &Path) -> Result<Dem> {
+    let mut decoder = Decoder::new(BufReader::new(File::open(path)?))?.with_limits(Limits::unlimited());
+    let (ncols, nrows) = decoder.dimensions()?;
+    let scale = decoder.get_tag_f64_vec(Tag::ModelPixelScaleTag)?;
+    let tie = decoder.get_tag_f64_vec(Tag::ModelTiepointTag)?;
+    if scale.len() < 2 || tie.len() < 6 {
+        return Err(anyhow!("{:?} has no usable tie point / pixel scale", path));
+    }
+    // GeoKey directory: 4-value header, then (key, location, count, value) entries
+    let geokeys = decoder.find_tag_unsigned_vec::<u16>(Tag::GeoKeyDirectoryTag)?.unwrap_or_default();
+    let geokey = |id: u16| geokeys.chunks_exact(4).skip(1).find(|k| k[0] == id && k[1] == 0).map(|k| k[3]);
+    let crs = match geokey(1024) {
+        Some(2) => DemCrs::Geographic,  // GTModelTypeGeoKey: ModelTypeGeographic
+        _ => DemCrs::Graph,
+    };
+    let pixel_is_point = geokey(1025) == Some(2);  // GTRasterTypeGeoKey: RasterPixelIsPoint
+    let nodata = match decoder.find_tag(Tag::GdalNodata)? {
+        Some(value) => value.into_string()?.trim_matches(char::from(0)).trim().parse().ok(),
+        None => None,
+    };
+    let values: Vec<f32> = match decoder.read_image()? {
+        DecodingResult::U8(v) => v.into_iter().map(|x| x as f32).collect(),
+        DecodingResult::U16(v) => v.into_iter().map(|x| x as f32).collect(),
+        DecodingResult::U32(v) => v.into_iter().map(|x| x as f32).collect(),
+        DecodingResult::U64(v) => v.into_iter().map(|x| x as f32).collect(),
+        DecodingResult::I8(v) => v.into_iter().map(|x| x as f32).collect(),
+        DecodingResult::I16(v) => v.into_iter().map(|x| x as f32).collect(),
+        DecodingResult::I32(v) => v.into_iter().map(|x| x as f32).collect(),
+        DecodingResult::I64(v) => v.into_iter().map(|x| x as f32).collect(),
+        DecodingResult::F32(v) => v,
+        DecodingResult::F64(v) => v.into_iter().map(|x| x as f32).collect(),
+    };
+    let (ncols, nrows) = (ncols as usize, nrows as usize);
+    if values.len() != ncols * nrows {
+        return Err(anyhow!("{:?} is not a single-band raster", path));
+    }
+    let half = if pixel_is_point { 0.0 } else { 0.5 };
+    Ok(Dem {
+        ncols,
+        nrows,
+        x0: tie[3] + (half - tie[0]) * scale[0],
+        y0: tie[4] - (half - tie[1]) * scale[1],
+        dx: scale[0],
+        dy: scale[1],
+        values,
+        nodata,
+        crs,
+    })
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ElevationSummary {
+    pub nodes_updated: usize,
+    pub nodes_outside: usize,
+    pub edges_updated: usize,
+}
+
+// Set node elevations from `source` (all nodes, or only those with a NaN
+// elevation unless `overwrite`), then recompute climb and slope of every
+// edge touching an updated node from its endpoint elevations.
+pub fn update_elevations<F>(graph: &mut RoadGraph, overwrite: bool, source: F) -> ElevationSummary
+where
+    F: Fn(&NodeData) -> Option<f64>,
+{
+    let mut summary = ElevationSummary::default();
+    let mut updated: HashSet<NodeIndex> = HashSet::new();
+    let nodes: Vec<NodeIndex> = graph.graph.node_indices().collect();
+    for n in nodes {
+        let node = &graph.graph[n];
+        if !overwrite && !node.elev.is_nan() {
+            continue;
+        }
+        match source(node) {
+            Some(elev) => {
+                graph.graph[n].elev = elev;
+                updated.insert(n);
+            }
+            None => summary.nodes_outside += 1,
+        }
+    }
+    summary.nodes_updated = updated.len();
+
+    let edges: Vec<_> = graph.graph.edge_indices().collect();
+    for e in edges {
+        let (u, v) = graph.graph.edge_endpoints(e).unwrap();
+        if !updated.contains(&u) && !updated.contains(&v) {
+            continue;
+        }
+        let climb = graph.graph[v].elev - graph.graph[u].elev;
+        let edge = &mut graph.graph[e];
+        edge.climb = climb;
+        edge.slope = if edge.length > 0.0 { climb / edge.length } else { 0.0 };
+        summary.edges_updated += 1;
+    }
+    summary
+}
+
+// Sample `dem` at every node (see `update_elevations`) and at the vertices of
+// edge polylines. Geographic rasters
+// need the graph in EPSG:3857 (or EPSG:4326) so node positions can be converted.
+pub fn attach_dem_elevations(graph: &mut RoadGraph, dem: &Dem, overwrite: bool) -> Result<ElevationSummary> {
+    let to_dem: fn(&NodeData) -> (f64, f64) = match (dem.crs, graph.meta.crs.as_deref()) {
+        (DemCrs::Graph, _) => |n| (n.x, n.y),
+        (DemCrs::Geographic, Some("EPSG:4326")) => |n| (n.x, n.y),
+        (DemCrs::Geographic, Some("EPSG:3857")) => |n| {
+            let (lat, lon) = unproject_web_mercator(n.x, n.y);
+            (lon, lat)
+        },
+        (DemCrs::Geographic, crs) => {
+            return Err(anyhow!("Cannot sample a lat/lon DEM for a graph in {}", crs.unwrap_or("an unknown CRS")))
+        }
+    };
+    let summary = update_elevations(graph, overwrite, |n| {
+        let (x, y) = to_dem(n);
+        dem.sample(x, y)
+    });
+    // Polyline vertices get their own samples, giving edges an intra-edge elevation shape
+    for edge in graph.graph.edge_weights_mut() {
+        let Some(geometry) = edge.geometry.as_mut() else { continue };
+        if geometry.elevs.is_some() && !overwrite {
+            continue;
+        }
+        let sampled: Option<Vec<f64>> = geometry
+            .points
+            .iter()
+            .map(|p| {
+                let (x, y) = to_dem(&NodeData { x: p[0], y: p[1], elev: 0.0 });
+                dem.sample(x, y)
+            })
+            .collect();
+        if let Some(elevs) = sampled {
+            geometry.elevs = Some(elevs);
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::{ingest_jsonl, IngestMode};
+
+    #[test]
+    fn dem_fills_jsonl_nodes_without_elevation() {
+        let path = std::env::temp_dir().join(format!("dem_fill_{}.jsonl", std::process::id()));
+        std::fs::write(
+            &path,
+            concat!(
+                "{\"type\":\"node\",\"id\":1,\"x\":0.0,\"y\":0.0}\n",
+                "{\"type\":\"node\",\"id\":2,\"x\":100.0,\"y\":0.0,\"elev\":50.0}\n",
+                "{\"type\":\"edge\",\"id\":1,\"u\":1,\"v\":2,\"length_m\":100.0,\"climb_m\":0.0,\"slope\":0.0}\n",
+            ),
+        )
+        .unwrap();
+        let ingested = ingest_jsonl(&path, IngestMode::FailFast);
+        std::fs::remove_file(&path).unwrap();
+        let (mut graph, _) = ingested.unwrap();
+        let node = |graph: &RoadGraph, id: u64| graph.graph[graph.node_map[&id]].elev;
+        assert!(node(&graph, 1).is_nan());
+
+        // Elevation x / 10 in graph coordinates
+        let dem = Dem {
+            ncols: 2,
+            nrows: 2,
+            x0: 0.0,
+            y0: 100.0,
+            dx: 100.0,
+            dy: 100.0,
+            values: vec![0.0, 10.0, 0.0, 10.0],
+            nodata: None,
+            crs: DemCrs::Graph,
+        };
+        let summary = attach_dem_elevations(&mut graph, &dem, false).unwrap();
+        assert_eq!((summary.nodes_updated, summary.edges_updated), (1, 1));
+        assert_eq!(node(&graph, 1), 0.0);
+        // Nodes with an elevation keep it without --dem-overwrite
+        assert_eq!(node(&graph, 2), 50.0);
+        let edge = graph.graph.edge_weights().next().unwrap();
+        assert_eq!((edge.climb, edge.slope), (50.0, 0.5));
+    }
+}
@@ -0,0 +1,102 @@
+use hashbrown::HashMap;
+use petgraph::stable_graph::NodeIndex;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::domain::{Query, RoadNetwork, RouteEnd};
+use crate::infrastructure::polyline_range_within;
+
+use super::start::edge_polyline;
+
+// Shortest distances from nodes into the area a route has to finish in, for
+// pruning partial routes that can no longer get there in the remaining length
+pub(super) struct EndRegion {
+    end: RouteEnd,
+    center: [f64; 2],
+    radius: f64,
+    dist: HashMap<NodeIndex, f64>,
+}
+
+// Min-heap entry on distance
+pub(super) struct Reach(pub(super) f64, pub(super) NodeIndex);
+
+impl PartialEq for Reach {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+impl Eq for Reach {}
+impl PartialOrd for Reach {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Reach {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0)
+    }
+}
+
+impl EndRegion {
+    // None when the query may end anywhere. Distances beyond `max_len` are not
+    // needed and are left out.
+    pub(super) fn new<N: RoadNetwork + ?Sized>(data: &N, query: &Query, max_len: f64) -> Option<Self> {
+        let end = query.end?;
+        let (center, radius) = match end {
+            RouteEnd::Loop { radius, at_center: true } => ([query.c.0, query.c.1], radius),
+            // The start is within D of the center, so the end is within D + radius of it
+            RouteEnd::Loop { radius, at_center: false } => ([query.c.0, query.c.1], radius + query.d),
+            RouteEnd::Region { center, radius } => ([center.0, center.1], radius),
+        };
+        // Seed each edge reaching the area with the distance along it to the
+        // first point inside, and nodes inside with 0 (a dead end has no edge
+        // leaving it to seed it); then search backwards along incoming edges
+        let mut dist: HashMap<NodeIndex, f64> = HashMap::new();
+        let mut heap = BinaryHeap::new();
+        let mut seed = |n: NodeIndex, d: f64| {
+            if dist.get(&n).is_none_or(|&known| d < known) {
+                dist.insert(n, d);
+                heap.push(Reach(d, n));
+            }
+        };
+        for (e_idx, _) in data.edges_near(center, radius) {
+            let (u, v) = data.edge_endpoints(e_idx);
+            for n in [u, v] {
+                let node = data.node(n);
+                if (node.x - center[0]).hypot(node.y - center[1]) <= radius {
+                    seed(n, 0.0);
+                }
+            }
+            let Some((lo, _)) = polyline_range_within(&center, &edge_polyline(data, e_idx), radius) else { continue };
+            seed(u, lo * data.edge(e_idx).length);
+        }
+        while let Some(Reach(d, n)) = heap.pop() {
+            if dist.get(&n).is_some_and(|&known| d > known) {
+                continue;
+            }
+            for (e_idx, source) in data.in_edges(n) {
+                let nd = d + data.edge(e_idx).length;
+                if nd <= max_len && dist.get(&source).is_none_or(|&known| nd < known) {
+                    dist.insert(source, nd);
+                    heap.push(Reach(nd, source));
+                }
+            }
+        }
+        Some(EndRegion { end, center, radius, dist })
+    }
+
+    // Lower bound on the length still needed from `n` to finish; infinite when
+    // the area is out of reach
+    pub(super) fn remaining(&self, n: NodeIndex) -> f64 {
+        self.dist.get(&n).copied().unwrap_or(f64::INFINITY)
+    }
+
+    // Whether a route from `start` to `end` finishes where it has to
+    pub(super) fn accepts(&self, start: [f64; 2], end: [f64; 2]) -> bool {
+        let target = match self.end {
+            RouteEnd::Loop { at_center: false, radius } => return (end[0] - start[0]).hypot(end[1] - start[1]) <= radius,
+            _ => self.center,
+        };
+        (end[0] - target[0]).hypot(end[1] - target[1]) <= self.radius
+    }
+}
@@ -0,0 +1,277 @@
+use anyhow::{anyhow, Result};
+use hashbrown::HashMap;
+use petgraph::stable_graph::{EdgeIndex, NodeIndex};
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::time::{Duration, Instant};
+
+use super::services::{edge_area, to_source_route, SearchConfig};
+use super::bound::AreaBound;
+use super::end::EndRegion;
+use super::start::{is_reverse, point_on_edge, virtual_starts};
+use crate::domain::{integral_abs_diff, Profile, Query, RoadNetwork, Route, RouteEnd};
+
+#[derive(Clone, Debug)]
+pub struct ExactLimits {
+    pub max_labels: usize,
+    pub time_limit: Option<Duration>,
+    // Labels at the same node merge when their length and relative elevation
+    // fall in the same bucket; the result is exact when edge lengths and climbs
+    // are multiples of these steps
+    pub length_step: f64,
+    pub elev_step: f64,
+}
+
+impl Default for ExactLimits {
+    fn default() -> Self {
+        ExactLimits { max_labels: 5_000_000, time_limit: Some(Duration::from_secs(60)), length_step: 0.01, elev_step: 0.01 }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ExactSearch {
+    pub route: Option<Route>,
+    // Area between the route and target profile with both starting at 0 (no vertical offset)
+    pub score: f64,
+    // False when a limit stopped the search; `route` is then the best found so far
+    pub optimal: bool,
+    pub labels: usize,
+}
+
+struct Label {
+    node: NodeIndex,
+    length: f64,
+    rel_elev: f64,
+    area: f64,
+    edge: EdgeIndex,
+    parent: Option<usize>,
+    first_edge: EdgeIndex,
+    first_fraction: f64,
+}
+
+// Min-heap entry on cumulative area
+struct Open(f64, usize);
+
+impl PartialEq for Open {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+impl Eq for Open {}
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0)
+    }
+}
+
+// Node, incoming edge, start leg, length bucket, relative elevation bucket
+type LabelKey = (NodeIndex, Option<EdgeIndex>, Option<(EdgeIndex, u64)>, i64, i64);
+
+struct LabelSet<'a> {
+    labels: Vec<Label>,
+    best_at: HashMap<LabelKey, usize>,
+    open: BinaryHeap<Open>,
+    limits: &'a ExactLimits,
+    // Keep labels arriving over different edges apart, so forbidding U-turns stays exact
+    by_edge: bool,
+    // Keep labels from different starts apart, so a loop that has to return to
+    // its own start stays exact
+    by_start: bool,
+}
+
+impl LabelSet<'_> {
+    fn key(&self, label: &Label) -> LabelKey {
+        let length = (label.length / self.limits.length_step).round() as i64;
+        let rel_elev = (label.rel_elev / self.limits.elev_step).round() as i64;
+        let start = self.by_start.then_some((label.first_edge, label.first_fraction.to_bits()));
+        (label.node, self.by_edge.then_some(label.edge), start, length, rel_elev)
+    }
+
+    // Queue the label, ordered by its area plus a lower bound on the area still
+    // to come, unless a label at the same state already has less area
+    fn push(&mut self, label: Label, bound: f64) {
+        let key = self.key(&label);
+        if let Some(&other) = self.best_at.get(&key) {
+            if self.labels[other].area <= label.area {
+                return;
+            }
+        }
+        self.best_at.insert(key, self.labels.len());
+        self.open.push(Open(label.area + bound, self.labels.len()));
+        self.labels.push(label);
+    }
+}
+
+// Area from `s` to the end of the target while the route stays at `rel_elev`
+fn tail_area(target: &Profile, s: f64, rel_elev: f64) -> f64 {
+    let l = target.total_length();
+    if s >= l {
+        return 0.0;
+    }
+    let mut knots = vec![s];
+    knots.extend(target.points.iter().map(|p| p.0).filter(|&k| k > s && k < l));
+    knots.push(l);
+    knots
+        .windows(2)
+        .map(|w| integral_abs_diff(w[1] - w[0], rel_elev - target.interpolate(w[0]), rel_elev - target.interpolate(w[1])))
+        .sum()
+}
+
+// Label-setting search over (node, length, relative elevation) states, best
+// first on cumulative area plus the `AreaBound` lower bound on the rest. A label
+// is dropped when another at the same state has less area, or when its bound
+// shows it cannot beat the best complete route; the search stops once no open
+// label can. Loops that end near their own start also keep each start in the
+// state. Like the beam, routes end at nodes within the length tolerance
+// `config` gives `find_route`, or part way along an edge where they are exactly
+// L long. Of the path policies only
+// `no_u_turns` is supported: forbidding edge or node reuse would make labels at
+// the same state incomparable.
+pub fn find_route_exact<N: RoadNetwork + ?Sized>(
+    data: &N,
+    query: &Query,
+    config: &SearchConfig,
+    limits: &ExactLimits,
+) -> Result<ExactSearch> {
+    if config.no_edge_reuse || config.no_node_reuse {
+        return Err(anyhow!("The exact search does not support the edge and node reuse policies"));
+    }
+    // Labels are ranked and pruned on the anchored area, so any other score would not be minimized
+    if config.matcher != "area" || config.use_offset {
+        return Err(anyhow!("The exact search minimizes the area without offset; use the area matcher with use_offset off"));
+    }
+    let mut result = ExactSearch { route: None, score: f64::INFINITY, optimal: true, labels: 0 };
+    let l = query.p.total_length();
+    if l == 0.0 {
+        return Ok(result);
+    }
+    let eps = config.tolerance(l);
+    let region = EndRegion::new(data, query, l + eps);
+    let reachable = |node: NodeIndex, length: f64| region.as_ref().is_none_or(|r| length + r.remaining(node) <= l + eps);
+    let started = Instant::now();
+    let mut search = LabelSet {
+        labels: Vec::new(),
+        best_at: HashMap::new(),
+        open: BinaryHeap::new(),
+        limits,
+        by_edge: config.no_u_turns,
+        by_start: matches!(query.end, Some(RouteEnd::Loop { at_center: false, .. })),
+    };
+
+    let starts = virtual_starts(data, [query.c.0, query.c.1], query.d);
+    let legs: Vec<(EdgeIndex, f64)> = starts.iter().flat_map(|start| start.out_legs(data)).collect();
+    let origins: Vec<(NodeIndex, f64)> = legs
+        .iter()
+        .map(|&(e_idx, fraction)| (data.edge_endpoints(e_idx).1, (1.0 - fraction) * data.edge(e_idx).length))
+        .collect();
+    let bound = AreaBound::new(data, &origins, l + eps, l - eps);
+    let remaining = |label: &Label| bound.remaining(&query.p, label.node, label.length, label.rel_elev);
+    let finishes = |start: [f64; 2], end: [f64; 2]| region.as_ref().is_none_or(|r| r.accepts(start, end));
+    // Best complete route so far: its edges, and the fractions it starts and ends at
+    let mut best: Option<(Vec<EdgeIndex>, f64, f64)> = None;
+    for (e_idx, fraction) in legs {
+        let edge = data.edge(e_idx);
+        if (1.0 - fraction) * edge.length > l {
+            // The whole route fits on this edge
+            let end = fraction + l / edge.length;
+            let score = edge_area(&edge, fraction, end, 0.0, 0.0, &query.p);
+            if score < result.score && finishes(point_on_edge(data, e_idx, fraction), point_on_edge(data, e_idx, end)) {
+                result.score = score;
+                best = Some((vec![e_idx], fraction, end));
+            }
+        }
+        let label = Label {
+            node: data.edge_endpoints(e_idx).1,
+            length: (1.0 - fraction) * edge.length,
+            rel_elev: edge.climb - edge.rel_elev_at(fraction),
+            area: edge_area(&edge, fraction, 1.0, 0.0, 0.0, &query.p),
+            edge: e_idx,
+            parent: None,
+            first_edge: e_idx,
+            first_fraction: fraction,
+        };
+        if reachable(label.node, label.length) {
+            let rest = remaining(&label);
+            search.push(label, rest);
+        }
+    }
+
+    while let Some(Open(priority, id)) = search.open.pop() {
+        if priority >= result.score {
+            break;
+        }
+        if search.best_at.get(&search.key(&search.labels[id])) != Some(&id) {
+            continue;  // Dominated after it was queued
+        }
+        if search.labels.len() > limits.max_labels || limits.time_limit.is_some_and(|t| started.elapsed() > t) {
+            result.optimal = false;
+            break;
+        }
+        let (node, length, rel_elev, area) =
+            (search.labels[id].node, search.labels[id].length, search.labels[id].rel_elev, search.labels[id].area);
+        let (first_edge, first_fraction) = (search.labels[id].first_edge, search.labels[id].first_fraction);
+        let start = point_on_edge(data, first_edge, first_fraction);
+        if (length - l).abs() <= eps {
+            let score = area + tail_area(&query.p, length, rel_elev);
+            let node = data.node(node);
+            if score < result.score && finishes(start, [node.x, node.y]) {
+                result.score = score;
+                best = Some((label_edges(&search.labels, id), first_fraction, 1.0));
+            }
+        }
+        for (e_idx, target) in data.out_edges(node) {
+            if config.no_u_turns && is_reverse(data, search.labels[id].edge, e_idx) {
+                continue;
+            }
+            let edge = data.edge(e_idx);
+            if length < l && length + edge.length > l {
+                // End part way along the edge, where the route is exactly L long
+                let t = (l - length) / edge.length;
+                let score = area + edge_area(&edge, 0.0, t, length, rel_elev, &query.p);
+                if score < result.score && finishes(start, point_on_edge(data, e_idx, t)) {
+                    result.score = score;
+                    let mut edges = label_edges(&search.labels, id);
+                    edges.push(e_idx);
+                    best = Some((edges, first_fraction, t));
+                }
+            }
+            if length + edge.length > l + eps || !reachable(target, length + edge.length) {
+                continue;
+            }
+            let label = Label {
+                node: target,
+                length: length + edge.length,
+                rel_elev: rel_elev + edge.climb,
+                area: area + edge_area(&edge, 0.0, 1.0, length, rel_elev, &query.p),
+                edge: e_idx,
+                parent: Some(id),
+                first_edge,
+                first_fraction,
+            };
+            let rest = remaining(&label);
+            if label.area + rest < result.score {
+                search.push(label, rest);
+            }
+        }
+    }
+    result.labels = search.labels.len();
+    result.route = best.map(|(edges, si, ti)| to_source_route(data, &edges, si, ti));
+    Ok(result)
+}
+
+// Edges of the path leading to label `id`, in order
+fn label_edges(labels: &[Label], id: usize) -> Vec<EdgeIndex> {
+    let mut edges = Vec::new();
+    let mut cur = Some(id);
+    while let Some(i) = cur {
+        edges.push(labels[i].edge);
+        cur = labels[i].parent;
+    }
+    edges.reverse();
+    edges
+}
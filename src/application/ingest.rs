@@ -0,0 +1,472 @@
+use anyhow::Result;
+use hashbrown::{HashMap, HashSet};
+use petgraph::stable_graph::StableGraph;
+use serde::Deserialize;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::domain::{EdgeData, EdgeGeometry, GraphMeta, LengthUnit, NodeData, RoadGraph};
+
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct MetaRecord {
+    pub crs: Option<String>,
+    pub units: Option<String>,
+    pub max_segment_m: Option<f64>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct NodeRecord {
+    pub id: u64,
+    pub x: f64,
+    pub y: f64,
+    // Missing elevations are NaN in the graph until `--dem` fills them
+    #[serde(default)]
+    pub elev: Option<f64>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct EdgeRecord {
+    pub id: u64,
+    pub u: u64,
+    pub v: u64,
+    // Always meters, whatever the meta record's `units`
+    pub length_m: f64,
+    pub climb_m: f64,
+    pub slope: f64,
+    // Polyline from u to v in source units, as [x, y] or [x, y, elev] vertices, endpoints included
+    #[serde(default)]
+    pub geometry: Option<Vec<Vec<f64>>>,
+}
+
+#[derive(Clone, Debug)]
+pub enum JsonlRecord {
+    Meta(MetaRecord),
+    Node(NodeRecord),
+    Edge(EdgeRecord),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IngestMode {
+    // Stop at the first problem (what `build_graph_from_jsonl` does)
+    FailFast,
+    // Keep going, skip bad records and report every problem found
+    CollectAll,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IssueKind {
+    Syntax,
+    UnknownRecordType,
+    InvalidField,
+    DuplicateNodeId,
+    DuplicateEdgeId,
+    DanglingEndpoint,
+    NonFiniteValue,
+    NegativeLength,
+    DuplicateMeta,
+    SegmentTooLong,
+    InvalidGeometry,
+}
+
+#[derive(Clone, Debug)]
+pub struct IngestIssue {
+    pub line: usize,
+    pub kind: IssueKind,
+    pub record_id: Option<u64>,
+    pub field: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for IngestIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}", self.line)?;
+        if let Some(id) = self.record_id {
+            write!(f, ", id {}", id)?;
+        }
+        if let Some(field) = &self.field {
+            write!(f, ", field `{}`", field)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for IngestIssue {}
+
+#[derive(Clone, Debug, Default)]
+pub struct ValidationReport {
+    pub lines: usize,
+    pub nodes: usize,
+    pub edges: usize,
+    pub issues: Vec<IngestIssue>,
+    // Problems that do not stop ingest, such as edges longer than `max_segment_m`
+    pub warnings: Vec<IngestIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    pub fn count(&self, kind: IssueKind) -> usize {
+        self.issues.iter().filter(|i| i.kind == kind).count()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} lines read, {} nodes and {} edges accepted, {} problem(s)",
+            self.lines,
+            self.nodes,
+            self.edges,
+            self.issues.len()
+        )?;
+        for issue in &self.issues {
+            writeln!(f, "  {}", issue)?;
+        }
+        if !self.warnings.is_empty() {
+            writeln!(f, "{} warning(s)", self.warnings.len())?;
+            for warning in &self.warnings {
+                writeln!(f, "  {}", warning)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Parse a single JSONL line into a typed record. The error carries the
+// record id (when it could be read) and the offending field.
+pub fn parse_record(line_no: usize, line: &str) -> std::result::Result<JsonlRecord, IngestIssue> {
+    let value: serde_json::Value = serde_json::from_str(line).map_err(|e| IngestIssue {
+        line: line_no,
+        kind: IssueKind::Syntax,
+        record_id: None,
+        field: None,
+        message: e.to_string(),
+    })?;
+    let record_id = value.get("id").and_then(|v| v.as_u64());
+    let issue = |kind, field: Option<String>, message: String| IngestIssue {
+        line: line_no,
+        kind,
+        record_id,
+        field,
+        message,
+    };
+    let record = match value.get("type").and_then(|t| t.as_str()) {
+        Some("meta") => typed(&value).map(JsonlRecord::Meta),
+        Some("node") => typed(&value).map(JsonlRecord::Node),
+        Some("edge") => typed(&value).map(JsonlRecord::Edge),
+        Some(other) => {
+            return Err(issue(
+                IssueKind::UnknownRecordType,
+                Some("type".to_string()),
+                format!("unknown record type {:?}", other),
+            ))
+        }
+        None => {
+            return Err(issue(
+                IssueKind::UnknownRecordType,
+                Some("type".to_string()),
+                "missing record type".to_string(),
+            ))
+        }
+    };
+    record.map_err(|(field, message)| issue(IssueKind::InvalidField, field, message))
+}
+
+fn typed<T: serde::de::DeserializeOwned>(value: &serde_json::Value) -> std::result::Result<T, (Option<String>, String)> {
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        let path = e.path().to_string();
+        let message = e.into_inner().to_string();
+        // Missing fields are reported against the parent, so pull the name out of the message
+        let field = if path != "." {
+            Some(path)
+        } else {
+            message
+                .strip_prefix("missing field `")
+                .and_then(|rest| rest.split('`').next())
+                .map(str::to_string)
+        };
+        (field, message)
+    })
+}
+
+// Checks that only need the record itself (no cross-record state).
+fn check_record(line_no: usize, record: &JsonlRecord) -> Vec<IngestIssue> {
+    let mut issues = Vec::new();
+    let mut non_finite = |id: u64, field: &str, value: f64| {
+        if !value.is_finite() {
+            issues.push(IngestIssue {
+                line: line_no,
+                kind: IssueKind::NonFiniteValue,
+                record_id: Some(id),
+                field: Some(field.to_string()),
+                message: format!("value {} is not finite", value),
+            });
+        }
+    };
+    match record {
+        JsonlRecord::Meta(m) => {
+            if let Some(max) = m.max_segment_m {
+                if !max.is_finite() || max <= 0.0 {
+                    issues.push(IngestIssue {
+                        line: line_no,
+                        kind: IssueKind::InvalidField,
+                        record_id: None,
+                        field: Some("max_segment_m".to_string()),
+                        message: format!("max_segment_m must be positive, got {}", max),
+                    });
+                }
+            }
+        }
+        JsonlRecord::Node(n) => {
+            non_finite(n.id, "x", n.x);
+            non_finite(n.id, "y", n.y);
+            if let Some(elev) = n.elev {
+                non_finite(n.id, "elev", elev);
+            }
+        }
+        JsonlRecord::Edge(e) => {
+            non_finite(e.id, "length_m", e.length_m);
+            non_finite(e.id, "climb_m", e.climb_m);
+            non_finite(e.id, "slope", e.slope);
+            if e.length_m < 0.0 {
+                issues.push(IngestIssue {
+                    line: line_no,
+                    kind: IssueKind::NegativeLength,
+                    record_id: Some(e.id),
+                    field: Some("length_m".to_string()),
+                    message: format!("negative length {}", e.length_m),
+                });
+            }
+            if let Some(geometry) = &e.geometry {
+                let dims = geometry.first().map(|p| p.len()).unwrap_or(0);
+                let problem = if geometry.len() < 2 {
+                    Some("needs at least two vertices".to_string())
+                } else if !(dims == 2 || dims == 3) || geometry.iter().any(|p| p.len() != dims) {
+                    Some("vertices must all be [x, y] or all be [x, y, elev]".to_string())
+                } else if geometry.iter().flatten().any(|v| !v.is_finite()) {
+                    Some("has non-finite coordinates".to_string())
+                } else {
+                    None
+                };
+                if let Some(message) = problem {
+                    issues.push(IngestIssue {
+                        line: line_no,
+                        kind: IssueKind::InvalidGeometry,
+                        record_id: Some(e.id),
+                        field: Some("geometry".to_string()),
+                        message: format!("geometry {}", message),
+                    });
+                }
+            }
+        }
+    }
+    issues
+}
+
+// Build an `EdgeGeometry` in meters, snapping its ends onto the edge's nodes.
+// Fails if an end is further than 1cm from its node.
+fn edge_geometry(vertices: &[Vec<f64>], to_m: f64, u: &NodeData, v: &NodeData) -> std::result::Result<EdgeGeometry, String> {
+    let mut points: Vec<[f64; 2]> = vertices.iter().map(|p| [p[0] * to_m, p[1] * to_m]).collect();
+    let elevs = (vertices[0].len() == 3).then(|| vertices.iter().map(|p| p[2] * to_m).collect());
+    let last = points.len() - 1;
+    for (i, node, name) in [(0, u, "u"), (last, v, "v")] {
+        let gap = ((points[i][0] - node.x).powi(2) + (points[i][1] - node.y).powi(2)).sqrt();
+        if gap > 0.01 {
+            return Err(format!("geometry end is {:.2}m away from node {}", gap, name));
+        }
+        points[i] = [node.x, node.y];
+    }
+    Ok(EdgeGeometry { points, elevs })
+}
+
+// Read a road graph JSONL file. In `FailFast` mode the first problem is
+// returned as an error; in `CollectAll` mode bad records are skipped and
+// every problem ends up in the report.
+pub fn ingest_jsonl(path: &Path, mode: IngestMode) -> Result<(RoadGraph, ValidationReport)> {
+    let file = File::open(path)?;
+    let reader = BufReader::new(file);
+    let mut graph = StableGraph::<NodeData, EdgeData>::new();
+    let mut node_map = HashMap::new();
+    let mut edge_ids = HashSet::new();
+    let mut pending_edges = Vec::new();
+    let mut report = ValidationReport::default();
+    let mut meta: Option<GraphMeta> = None;
+
+    let record_issue = |report: &mut ValidationReport, issue: IngestIssue| -> Result<()> {
+        match mode {
+            IngestMode::FailFast => Err(issue.into()),
+            IngestMode::CollectAll => {
+                report.issues.push(issue);
+                Ok(())
+            }
+        }
+    };
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_no = i + 1;
+        report.lines = line_no;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = match parse_record(line_no, &line) {
+            Ok(record) => record,
+            Err(issue) => {
+                record_issue(&mut report, issue)?;
+                continue;
+            }
+        };
+        let issues = check_record(line_no, &record);
+        let rejected = !issues.is_empty();
+        for issue in issues {
+            record_issue(&mut report, issue)?;
+        }
+        if rejected {
+            continue;
+        }
+        match record {
+            JsonlRecord::Meta(m) => {
+                if meta.is_some() {
+                    record_issue(&mut report, IngestIssue {
+                        line: line_no,
+                        kind: IssueKind::DuplicateMeta,
+                        record_id: None,
+                        field: None,
+                        message: "more than one meta record".to_string(),
+                    })?;
+                    continue;
+                }
+                let units = match m.units.as_deref().map(str::parse::<LengthUnit>).transpose() {
+                    Ok(units) => units.unwrap_or_default(),
+                    Err(message) => {
+                        record_issue(&mut report, IngestIssue {
+                            line: line_no,
+                            kind: IssueKind::InvalidField,
+                            record_id: None,
+                            field: Some("units".to_string()),
+                            message,
+                        })?;
+                        continue;
+                    }
+                };
+                meta = Some(GraphMeta { crs: m.crs, units, max_segment_m: m.max_segment_m });
+            }
+            JsonlRecord::Node(n) => {
+                if node_map.contains_key(&n.id) {
+                    record_issue(&mut report, IngestIssue {
+                        line: line_no,
+                        kind: IssueKind::DuplicateNodeId,
+                        record_id: Some(n.id),
+                        field: Some("id".to_string()),
+                        message: format!("duplicate node id {}", n.id),
+                    })?;
+                    continue;
+                }
+                let idx = graph.add_node(NodeData { x: n.x, y: n.y, elev: n.elev.unwrap_or(f64::NAN) });
+                node_map.insert(n.id, idx);
+            }
+            JsonlRecord::Edge(e) => {
+                if !edge_ids.insert(e.id) {
+                    record_issue(&mut report, IngestIssue {
+                        line: line_no,
+                        kind: IssueKind::DuplicateEdgeId,
+                        record_id: Some(e.id),
+                        field: Some("id".to_string()),
+                        message: format!("duplicate edge id {}", e.id),
+                    })?;
+                    continue;
+                }
+                pending_edges.push((line_no, e));
+            }
+        }
+    }
+
+    // Everything is stored in meters; the meta record may appear anywhere in the file.
+    // Coordinates and elevations are in source units, `length_m` and `climb_m` already in meters
+    let meta = meta.unwrap_or_default();
+    let to_m = meta.units.to_meters();
+    if to_m != 1.0 {
+        for node in graph.node_weights_mut() {
+            node.x *= to_m;
+            node.y *= to_m;
+            node.elev *= to_m;
+        }
+    }
+
+    // Edges are resolved once every node is known, so node/edge order in the file does not matter
+    for (line_no, e) in pending_edges {
+        let mut endpoints = [None, None];
+        for (slot, (field, node_id)) in [("u", e.u), ("v", e.v)].into_iter().enumerate() {
+            endpoints[slot] = node_map.get(&node_id).copied();
+            if endpoints[slot].is_none() {
+                record_issue(&mut report, IngestIssue {
+                    line: line_no,
+                    kind: IssueKind::DanglingEndpoint,
+                    record_id: Some(e.id),
+                    field: Some(field.to_string()),
+                    message: format!("references unknown node {}", node_id),
+                })?;
+            }
+        }
+        if let [Some(u_idx), Some(v_idx)] = endpoints {
+            let geometry = match &e.geometry {
+                Some(vertices) => match edge_geometry(vertices, to_m, &graph[u_idx], &graph[v_idx]) {
+                    Ok(geometry) => Some(geometry),
+                    Err(message) => {
+                        record_issue(&mut report, IngestIssue {
+                            line: line_no,
+                            kind: IssueKind::InvalidGeometry,
+                            record_id: Some(e.id),
+                            field: Some("geometry".to_string()),
+                            message,
+                        })?;
+                        continue;
+                    }
+                },
+                None => None,
+            };
+            let edge = EdgeData {
+                id: e.id,
+                length: e.length_m,
+                climb: e.climb_m,
+                slope: e.slope,
+                origin: None,
+                geometry,
+            };
+            if let Some(max) = meta.max_segment_m {
+                if edge.length > max + 1e-6 {
+                    report.warnings.push(IngestIssue {
+                        line: line_no,
+                        kind: IssueKind::SegmentTooLong,
+                        record_id: Some(e.id),
+                        field: Some("length_m".to_string()),
+                        message: format!("length {:.1}m exceeds max_segment_m {}", edge.length, max),
+                    });
+                }
+            }
+            graph.add_edge(u_idx, v_idx, edge);
+        }
+    }
+
+    report.issues.sort_by_key(|issue| issue.line);
+    report.warnings.sort_by_key(|issue| issue.line);
+    report.nodes = graph.node_count();
+    report.edges = graph.edge_count();
+    Ok((RoadGraph::new(graph, node_map, meta), report))
+}
+
+pub fn build_graph_from_jsonl(path: &Path) -> Result<RoadGraph> {
+    let (graph, _) = ingest_jsonl(path, IngestMode::FailFast)?;
+    Ok(graph)
+}
+
+// Run a full pass over the file and return every problem found.
+pub fn validate_jsonl(path: &Path) -> Result<ValidationReport> {
+    let (_, report) = ingest_jsonl(path, IngestMode::CollectAll)?;
+    Ok(report)
+}
@@ -1,3 +1,23 @@
+mod bound;
+mod check;
+mod decode;
+mod dem;
+mod end;
+mod exact;
+mod ingest;
+mod osm;
+mod osm_pbf;
 mod services;
+mod split;
+mod start;
 
-pub use services::*;
\ No newline at end of file
+pub use bound::*;
+pub use check::*;
+pub use decode::*;
+pub use dem::*;
+pub use exact::*;
+pub use ingest::*;
+pub use osm::*;
+pub use services::*;
+pub use split::*;
+pub use start::*;
\ No newline at end of file
@@ -0,0 +1,283 @@
+use anyhow::{anyhow, Result};
+use hashbrown::HashMap;
+use petgraph::stable_graph::{NodeIndex, StableGraph};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use super::osm_pbf::read_osm_pbf;
+use crate::domain::{EdgeData, GraphMeta, LengthUnit, NodeData, RoadGraph};
+
+// highway=* values treated as rideable roads
+const ROUTABLE_HIGHWAYS: [&str; 21] = [
+    "motorway", "motorway_link", "trunk", "trunk_link", "primary", "primary_link",
+    "secondary", "secondary_link", "tertiary", "tertiary_link", "unclassified",
+    "residential", "living_street", "service", "road", "track", "cycleway", "path",
+    "bridleway", "busway", "bus_guideway",
+];
+// Only routable when bicycles are explicitly allowed
+const BICYCLE_OPTIONAL_HIGHWAYS: [&str; 3] = ["footway", "pedestrian", "steps"];
+
+const EARTH_RADIUS_M: f64 = 6_378_137.0;  // WGS84 semi-major axis, as used by EPSG:3857
+const MEAN_EARTH_RADIUS_M: f64 = 6_371_008.8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OsmFormat {
+    Xml,
+    Pbf,
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct OsmNode {
+    pub lat: f64,
+    pub lon: f64,
+    pub ele: Option<f64>,
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct OsmWay {
+    pub refs: Vec<i64>,
+    pub direction: WayDirection,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum WayDirection {
+    Both,
+    Forward,
+    Backward,
+}
+
+pub(super) type Tag = (String, String);
+
+#[derive(Default)]
+pub(super) struct OsmData {
+    pub nodes: HashMap<i64, OsmNode>,
+    pub ways: Vec<OsmWay>,
+    pub ways_seen: usize,
+}
+
+impl OsmData {
+    // Keep the way only if it is a routable highway; tags are not stored beyond that
+    pub(super) fn add_way(&mut self, refs: Vec<i64>, tags: Vec<Tag>) {
+        self.ways_seen += 1;
+        let tag = |key: &str| tags.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+        let Some(highway) = tag("highway") else { return };
+        let bicycle = tag("bicycle");
+        let routable = ROUTABLE_HIGHWAYS.contains(&highway)
+            || (BICYCLE_OPTIONAL_HIGHWAYS.contains(&highway) && matches!(bicycle, Some("yes" | "designated")));
+        if !routable || matches!(tag("access"), Some("no" | "private")) || bicycle == Some("no") || refs.len() < 2 {
+            return;
+        }
+        let oneway = tag("oneway:bicycle").or(tag("oneway"));
+        let direction = match oneway {
+            Some("yes" | "true" | "1") => WayDirection::Forward,
+            Some("-1" | "reverse") => WayDirection::Backward,
+            Some("no" | "false" | "0") => WayDirection::Both,
+            _ if tag("junction") == Some("roundabout") || highway == "motorway" => WayDirection::Forward,
+            _ => WayDirection::Both,
+        };
+        self.ways.push(OsmWay { refs, direction });
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OsmSummary {
+    pub ways_seen: usize,
+    pub ways_kept: usize,
+    pub nodes: usize,
+    pub edges: usize,
+    pub nodes_without_elevation: usize,
+    pub missing_node_refs: usize,
+}
+
+pub(super) fn parse_ele(value: &str) -> Option<f64> {
+    value.trim().trim_end_matches('m').trim().parse().ok().filter(|e: &f64| e.is_finite())
+}
+
+// Web Mercator (EPSG:3857) projection of WGS84 lat/lon in degrees
+pub fn project_web_mercator(lat: f64, lon: f64) -> (f64, f64) {
+    let x = EARTH_RADIUS_M * lon.to_radians();
+    let y = EARTH_RADIUS_M * (std::f64::consts::FRAC_PI_4 + lat.to_radians() / 2.0).tan().ln();
+    (x, y)
+}
+
+// Inverse of `project_web_mercator`, returning (lat, lon) in degrees
+pub fn unproject_web_mercator(x: f64, y: f64) -> (f64, f64) {
+    let lon = (x / EARTH_RADIUS_M).to_degrees();
+    let lat = (2.0 * (y / EARTH_RADIUS_M).exp().atan() - std::f64::consts::FRAC_PI_2).to_degrees();
+    (lat, lon)
+}
+
+// Great-circle distance in meters; Web Mercator distances are stretched by 1/cos(lat)
+pub fn haversine_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_phi = phi2 - phi1;
+    let d_lambda = (lon2 - lon1).to_radians();
+    let a = (d_phi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (d_lambda / 2.0).sin().powi(2);
+    2.0 * MEAN_EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+pub fn detect_osm_format(path: &Path) -> Result<OsmFormat> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("pbf") => return Ok(OsmFormat::Pbf),
+        Some("osm") | Some("xml") => return Ok(OsmFormat::Xml),
+        _ => {}
+    }
+    // PBF starts with a 4-byte length, XML with '<' after optional whitespace/BOM
+    let mut head = [0u8; 64];
+    let n = File::open(path)?.read(&mut head)?;
+    let first = head[..n].iter().find(|b| !b.is_ascii_whitespace() && **b != 0xEF && **b != 0xBB && **b != 0xBF);
+    Ok(if first == Some(&b'<') { OsmFormat::Xml } else { OsmFormat::Pbf })
+}
+
+pub fn build_graph_from_osm(path: &Path) -> Result<(RoadGraph, OsmSummary)> {
+    let reader = BufReader::new(File::open(path)?);
+    let data = match detect_osm_format(path)? {
+        OsmFormat::Xml => read_osm_xml(reader)?,
+        OsmFormat::Pbf => read_osm_pbf(reader)?,
+    };
+    Ok(build_graph_from_osm_data(&data))
+}
+
+// Every consecutive node pair of a kept way becomes one edge per allowed
+// direction. Ids are assigned in order, forward edge before its reverse.
+fn build_graph_from_osm_data(data: &OsmData) -> (RoadGraph, OsmSummary) {
+    let mut graph = StableGraph::<NodeData, EdgeData>::new();
+    let mut node_map: HashMap<u64, NodeIndex> = HashMap::new();
+    let mut summary = OsmSummary { ways_seen: data.ways_seen, ways_kept: data.ways.len(), ..Default::default() };
+    let mut next_edge_id = 1u64;
+
+    let mut node_index = |graph: &mut StableGraph<NodeData, EdgeData>, summary: &mut OsmSummary, id: i64| {
+        let node = data.nodes.get(&id)?;
+        let idx = *node_map.entry(id as u64).or_insert_with(|| {
+            let (x, y) = project_web_mercator(node.lat, node.lon);
+            if node.ele.is_none() {
+                summary.nodes_without_elevation += 1;
+            }
+            graph.add_node(NodeData { x, y, elev: node.ele.unwrap_or(f64::NAN) })
+        });
+        Some((idx, node))
+    };
+
+    for way in &data.ways {
+        for pair in way.refs.windows(2) {
+            if pair[0] == pair[1] {
+                continue;
+            }
+            let a = node_index(&mut graph, &mut summary, pair[0]);
+            let b = node_index(&mut graph, &mut summary, pair[1]);
+            // Clipped extracts reference nodes outside the bounding box; drop those segments
+            let (Some((a_idx, a)), Some((b_idx, b))) = (a, b) else {
+                summary.missing_node_refs += 1;
+                continue;
+            };
+            let length = haversine_m(a.lat, a.lon, b.lat, b.lon);
+            // NaN when either end has no ele tag; filled in by `update_elevations`
+            let climb = b.ele.unwrap_or(f64::NAN) - a.ele.unwrap_or(f64::NAN);
+            let slope = if length > 0.0 { climb / length } else { 0.0 };
+            if way.direction != WayDirection::Backward {
+                graph.add_edge(a_idx, b_idx, EdgeData { id: next_edge_id, length, climb, slope, origin: None, geometry: None });
+                next_edge_id += 1;
+            }
+            if way.direction != WayDirection::Forward {
+                graph.add_edge(b_idx, a_idx, EdgeData { id: next_edge_id, length, climb: -climb, slope: -slope, origin: None, geometry: None });
+                next_edge_id += 1;
+            }
+        }
+    }
+
+    summary.nodes = graph.node_count();
+    summary.edges = graph.edge_count();
+    let meta = GraphMeta { crs: Some("EPSG:3857".to_string()), units: LengthUnit::Meters, max_segment_m: None };
+    (RoadGraph::new(graph, node_map, meta), summary)
+}
+
+pub(super) fn read_osm_xml<R: BufRead>(reader: R) -> Result<OsmData> {
+    let mut xml = Reader::from_reader(reader);
+    let mut buf = Vec::new();
+    let mut data = OsmData::default();
+    // The element currently open: a node (id, lat, lon, ele) or a way (refs, tags)
+    let mut node: Option<(i64, OsmNode)> = None;
+    let mut way: Option<(Vec<i64>, Vec<Tag>)> = None;
+
+    loop {
+        let event = xml.read_event_into(&mut buf).map_err(|e| anyhow!("OSM XML error at byte {}: {}", xml.buffer_position(), e))?;
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let empty = matches!(event, Event::Empty(_));
+                match e.name().as_ref() {
+                    b"node" => {
+                        let attrs = attributes(e)?;
+                        let id = required(&attrs, "id")?;
+                        let parsed = (id, OsmNode { lat: required(&attrs, "lat")?, lon: required(&attrs, "lon")?, ele: None });
+                        if empty {
+                            data.nodes.insert(parsed.0, parsed.1);
+                        } else {
+                            node = Some(parsed);
+                        }
+                    }
+                    b"way" => {
+                        if empty {
+                            data.add_way(Vec::new(), Vec::new());
+                        } else {
+                            way = Some((Vec::new(), Vec::new()));
+                        }
+                    }
+                    b"nd" => {
+                        if let Some((refs, _)) = way.as_mut() {
+                            refs.push(required(&attributes(e)?, "ref")?);
+                        }
+                    }
+                    b"tag" => {
+                        let attrs = attributes(e)?;
+                        let (Some(k), Some(v)) = (attrs.get("k"), attrs.get("v")) else { continue };
+                        if let Some((_, n)) = node.as_mut() {
+                            if k == "ele" {
+                                n.ele = parse_ele(v);
+                            }
+                        } else if let Some((_, tags)) = way.as_mut() {
+                            tags.push((k.clone(), v.clone()));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::End(ref e) => match e.name().as_ref() {
+                b"node" => {
+                    if let Some((id, n)) = node.take() {
+                        data.nodes.insert(id, n);
+                    }
+                }
+                b"way" => {
+                    if let Some((refs, tags)) = way.take() {
+                        data.add_way(refs, tags);
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(data)
+}
+
+fn attributes(e: &BytesStart) -> Result<HashMap<String, String>> {
+    let mut attrs = HashMap::new();
+    for attr in e.attributes() {
+        let attr = attr?;
+        let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+        attrs.insert(key, attr.unescape_value()?.into_owned());
+    }
+    Ok(attrs)
+}
+
+fn required<T: std::str::FromStr>(attrs: &HashMap<String, String>, key: &str) -> Result<T> {
+    attrs
+        .get(key)
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| anyhow!("OSM element is missing a valid {:?} attribute", key))
+}
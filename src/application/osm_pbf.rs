@@ -0,0 +1,290 @@
+use anyhow::{anyhow, Result};
+use flate2::read::ZlibDecoder;
+use prost::Message;
+use std::io::{self, Read};
+
+use super::osm::{parse_ele, OsmData, OsmNode, Tag};
+
+// Just the parts of the OSM PBF schema (fileformat.proto / osmformat.proto) the importer reads.
+// Fields not declared here (info, relations, changesets, ...) are skipped by the decoder.
+
+#[derive(Clone, PartialEq, Message)]
+struct BlobHeader {
+    #[prost(string, required, tag = "1")]
+    r#type: String,
+    #[prost(int32, required, tag = "3")]
+    datasize: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Blob {
+    #[prost(bytes = "vec", optional, tag = "1")]
+    raw: Option<Vec<u8>>,
+    #[prost(int32, optional, tag = "2")]
+    raw_size: Option<i32>,
+    #[prost(bytes = "vec", optional, tag = "3")]
+    zlib_data: Option<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct HeaderBlock {
+    #[prost(string, repeated, tag = "4")]
+    required_features: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct StringTable {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    s: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct PrimitiveBlock {
+    #[prost(message, required, tag = "1")]
+    stringtable: StringTable,
+    #[prost(message, repeated, tag = "2")]
+    primitivegroup: Vec<PrimitiveGroup>,
+    #[prost(int32, optional, tag = "17")]
+    granularity: Option<i32>,
+    #[prost(int64, optional, tag = "19")]
+    lat_offset: Option<i64>,
+    #[prost(int64, optional, tag = "20")]
+    lon_offset: Option<i64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct PrimitiveGroup {
+    #[prost(message, repeated, tag = "1")]
+    nodes: Vec<Node>,
+    #[prost(message, optional, tag = "2")]
+    dense: Option<DenseNodes>,
+    #[prost(message, repeated, tag = "3")]
+    ways: Vec<Way>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Node {
+    #[prost(sint64, required, tag = "1")]
+    id: i64,
+    #[prost(uint32, repeated, tag = "2")]
+    keys: Vec<u32>,
+    #[prost(uint32, repeated, tag = "3")]
+    vals: Vec<u32>,
+    #[prost(sint64, required, tag = "8")]
+    lat: i64,
+    #[prost(sint64, required, tag = "9")]
+    lon: i64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct DenseNodes {
+    #[prost(sint64, repeated, tag = "1")]
+    id: Vec<i64>,
+    #[prost(sint64, repeated, tag = "8")]
+    lat: Vec<i64>,
+    #[prost(sint64, repeated, tag = "9")]
+    lon: Vec<i64>,
+    #[prost(int32, repeated, tag = "10")]
+    keys_vals: Vec<i32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Way {
+    #[prost(uint32, repeated, tag = "2")]
+    keys: Vec<u32>,
+    #[prost(uint32, repeated, tag = "3")]
+    vals: Vec<u32>,
+    #[prost(sint64, repeated, tag = "8")]
+    refs: Vec<i64>,
+}
+
+const SUPPORTED_FEATURES: [&str; 2] = ["OsmSchema-V0.6", "DenseNodes"];
+// Size limits from the PBF spec, checked before allocating
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+const MAX_BLOB_SIZE: usize = 32 * 1024 * 1024;
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(super) fn read_osm_pbf<R: Read>(mut reader: R) -> Result<OsmData> {
+    let mut data = OsmData::default();
+    loop {
+        let mut len_buf = [0u8; 4];
+        match reader.read_exact(&mut len_buf) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let header_size = u32::from_be_bytes(len_buf) as usize;
+        if header_size > MAX_HEADER_SIZE {
+            return Err(invalid(format!("PBF blob header of {} bytes exceeds {}", header_size, MAX_HEADER_SIZE)).into());
+        }
+        let mut header_buf = vec![0u8; header_size];
+        reader.read_exact(&mut header_buf)?;
+        let header = BlobHeader::decode(&header_buf[..])?;
+        let datasize = usize::try_from(header.datasize)
+            .ok()
+            .filter(|&size| size <= MAX_BLOB_SIZE)
+            .ok_or_else(|| invalid(format!("PBF blob size {} is outside 0..={}", header.datasize, MAX_BLOB_SIZE)))?;
+        let mut blob_buf = vec![0u8; datasize];
+        reader.read_exact(&mut blob_buf)?;
+        let blob = Blob::decode(&blob_buf[..])?;
+        let bytes = blob_bytes(blob)?;
+        match header.r#type.as_str() {
+            "OSMHeader" => {
+                let block = HeaderBlock::decode(&bytes[..])?;
+                for feature in &block.required_features {
+                    if !SUPPORTED_FEATURES.contains(&feature.as_str()) {
+                        return Err(anyhow!("Unsupported PBF feature {:?}", feature));
+                    }
+                }
+            }
+            "OSMData" => read_primitive_block(&PrimitiveBlock::decode(&bytes[..])?, &mut data)?,
+            _ => {} // Unknown blob types are skipped, as the spec allows
+        }
+    }
+    Ok(data)
+}
+
+fn blob_bytes(blob: Blob) -> Result<Vec<u8>> {
+    if let Some(raw) = blob.raw {
+        return Ok(raw);
+    }
+    if let Some(zlib) = blob.zlib_data {
+        let mut out = Vec::with_capacity((blob.raw_size.unwrap_or(0).max(0) as usize).min(MAX_BLOB_SIZE));
+        ZlibDecoder::new(&zlib[..]).take(MAX_BLOB_SIZE as u64 + 1).read_to_end(&mut out)?;
+        if out.len() > MAX_BLOB_SIZE {
+            return Err(invalid(format!("PBF blob inflates past {} bytes", MAX_BLOB_SIZE)).into());
+        }
+        return Ok(out);
+    }
+    Err(anyhow!("Unsupported PBF blob compression (only raw and zlib are supported)"))
+}
+
+fn read_primitive_block(block: &PrimitiveBlock, data: &mut OsmData) -> io::Result<()> {
+    let strings: Vec<String> = block.stringtable.s.iter().map(|s| String::from_utf8_lossy(s).into_owned()).collect();
+    let granularity = block.granularity.unwrap_or(100) as i64;
+    let lat_offset = block.lat_offset.unwrap_or(0);
+    let lon_offset = block.lon_offset.unwrap_or(0);
+    // Wrapping, so corrupt values give bad coordinates rather than an overflow panic
+    let degrees = |offset: i64, value: i64| 1e-9 * offset.wrapping_add(granularity.wrapping_mul(value)) as f64;
+    let string = |i: u32| strings.get(i as usize).map_or("", String::as_str);
+    let tag = |k: u32, v: u32| (string(k), string(v));
+
+    for group in &block.primitivegroup {
+        for node in &group.nodes {
+            let ele = node.keys.iter().zip(&node.vals).map(|(&k, &v)| tag(k, v)).find(|(k, _)| *k == "ele");
+            data.nodes.insert(node.id, OsmNode {
+                lat: degrees(lat_offset, node.lat),
+                lon: degrees(lon_offset, node.lon),
+                ele: ele.and_then(|(_, v)| parse_ele(v)),
+            });
+        }
+        if let Some(dense) = &group.dense {
+            if dense.lat.len() != dense.id.len() || dense.lon.len() != dense.id.len() {
+                return Err(invalid(format!(
+                    "PBF dense nodes have {} ids but {} latitudes and {} longitudes",
+                    dense.id.len(),
+                    dense.lat.len(),
+                    dense.lon.len()
+                )));
+            }
+            // Ids and coordinates are delta coded; keys_vals is a flat k,v,...,0 list per node
+            let (mut id, mut lat, mut lon) = (0i64, 0i64, 0i64);
+            let mut kv = dense.keys_vals.iter();
+            for ((&d_id, &d_lat), &d_lon) in dense.id.iter().zip(&dense.lat).zip(&dense.lon) {
+                id = id.wrapping_add(d_id);
+                lat = lat.wrapping_add(d_lat);
+                lon = lon.wrapping_add(d_lon);
+                let mut ele = None;
+                while let Some(&k) = kv.next() {
+                    if k == 0 {
+                        break;
+                    }
+                    let v = kv.next().copied().unwrap_or(0);
+                    let (key, value) = tag(k as u32, v as u32);
+                    if key == "ele" {
+                        ele = parse_ele(value);
+                    }
+                }
+                data.nodes.insert(id, OsmNode {
+                    lat: degrees(lat_offset, lat),
+                    lon: degrees(lon_offset, lon),
+                    ele,
+                });
+            }
+        }
+        for way in &group.ways {
+            let tags: Vec<Tag> = way
+                .keys
+                .iter()
+                .zip(&way.vals)
+                .map(|(&k, &v)| {
+                    let (k, v) = tag(k, v);
+                    (k.to_string(), v.to_string())
+                })
+                .collect();
+            let mut node_ref = 0i64;
+            let refs = way
+                .refs
+                .iter()
+                .map(|delta| {
+                    node_ref = node_ref.wrapping_add(*delta);
+                    node_ref
+                })
+                .collect();
+            data.add_way(refs, tags);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(kind: &str, datasize: i32, blob: &[u8]) -> Vec<u8> {
+        let header = BlobHeader { r#type: kind.to_string(), datasize }.encode_to_vec();
+        let mut out = (header.len() as u32).to_be_bytes().to_vec();
+        out.extend(header);
+        out.extend(blob);
+        out
+    }
+
+    fn data_blob(dense: DenseNodes) -> Vec<u8> {
+        let block = PrimitiveBlock {
+            stringtable: StringTable { s: vec![Vec::new()] },
+            primitivegroup: vec![PrimitiveGroup { nodes: Vec::new(), dense: Some(dense), ways: Vec::new() }],
+            granularity: None,
+            lat_offset: None,
+            lon_offset: None,
+        };
+        Blob { raw: Some(block.encode_to_vec()), raw_size: None, zlib_data: None }.encode_to_vec()
+    }
+
+    #[test]
+    fn reads_dense_nodes() {
+        let blob = data_blob(DenseNodes { id: vec![5, 1], lat: vec![470_000_000, 10], lon: vec![80_000_000, -10], keys_vals: Vec::new() });
+        let data = read_osm_pbf(&frame("OSMData", blob.len() as i32, &blob)[..]).unwrap();
+        assert_eq!(data.nodes.len(), 2);
+        assert!((data.nodes[&6].lat - 47.000001).abs() < 1e-9);
+        assert!((data.nodes[&6].lon - 7.999999).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rejects_dense_nodes_with_missing_coordinates() {
+        let blob = data_blob(DenseNodes { id: vec![1, 1, 1], lat: vec![0, 0], lon: vec![0, 0, 0], keys_vals: Vec::new() });
+        let Err(err) = read_osm_pbf(&frame("OSMData", blob.len() as i32, &blob)[..]) else { panic!("accepted a short lat array") };
+        assert!(err.to_string().contains("3 ids but 2 latitudes"), "{err}");
+    }
+
+    #[test]
+    fn rejects_bad_sizes_before_allocating() {
+        for datasize in [-1, i32::MAX] {
+            assert!(read_osm_pbf(&frame("OSMData", datasize, &[])[..]).is_err());
+        }
+        let huge_header = u32::MAX.to_be_bytes();
+        assert!(read_osm_pbf(&huge_header[..]).is_err());
+    }
+}
@@ -1,60 +1,161 @@
 use anyhow::{anyhow, Result};
-use hashbrown::HashMap;
-use petgraph::stable_graph::{EdgeIndex, NodeIndex, StableGraph};
+use hashbrown::HashSet;
+use petgraph::stable_graph::{EdgeIndex, NodeIndex};
 use rstar::RTree;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
 
-use crate::domain::{integral_abs_diff, AreaMatcher, EdgeData, NodeData, Profile, ProfileMatcher, Query, RoadGraph, Route};
-use crate::infrastructure::{AppData, SpatialEdge, project_point_to_segment, distance_to_point};
+use crate::domain::{
+    integral_abs_diff, matcher_by_name, stretch_pieces, EdgeData, MatcherOptions, Profile, ProfileMatcher, Query, RoadGraph, RoadNetwork, Route,
+    RouteMatch, MATCHERS,
+};
+use crate::infrastructure::SpatialEdge;
+
+use super::bound::AreaBound;
+use super::end::EndRegion;
+use super::start::{is_reverse, point_on_edge, virtual_starts};
 
 #[derive(Clone)]
 struct PartialPath {
     node: NodeIndex,
     length: f64,
-    cum_area: f64,
+    cum_cost: f64,
     rel_elev: f64,
-    path: Vec<(EdgeIndex, f64)>,  // (edge_idx, fraction_end)
+    tail: Option<usize>,  // Last step after the first edge, in `Steps`
     first_fraction: f64,
     first_edge_idx: Option<EdgeIndex>,
+    // Where the first edge ends; below 1.0 only when the route lies within it
+    first_end: f64,
+    // Bit sets of hashed edge and node indices on the path, so reuse checks only
+    // look further when a bit is already taken
+    edge_bits: u64,
+    node_bits: u64,
 }
 
-pub fn build_graph_from_jsonl(path: &Path) -> Result<RoadGraph> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let mut graph = StableGraph::<NodeData, EdgeData>::new();
-    let mut node_map = HashMap::new();
-
-    for line in reader.lines() {
-        let line = line?;
-        let record: serde_json::Value = serde_json::from_str(&line)?;
-        match record["type"].as_str() {
-            Some("meta") => {},  // Ignore
-            Some("node") => {
-                let id = record["id"].as_u64().ok_or(anyhow!("Invalid node id"))?;
-                let x = record["x"].as_f64().ok_or(anyhow!("Invalid x"))?;
-                let y = record["y"].as_f64().ok_or(anyhow!("Invalid y"))?;
-                let elev = record["elev"].as_f64().ok_or(anyhow!("Invalid elev"))?;
-                let idx = graph.add_node(NodeData { x, y, elev });
-                node_map.insert(id, idx);
-            }
-            Some("edge") => {
-                let id = record["id"].as_u64().ok_or(anyhow!("Invalid edge id"))?;
-                let u = record["u"].as_u64().ok_or(anyhow!("Invalid u"))?;
-                let v = record["v"].as_u64().ok_or(anyhow!("Invalid v"))?;
-                let length = record["length_m"].as_f64().ok_or(anyhow!("Invalid length"))?;
-                let climb = record["climb_m"].as_f64().ok_or(anyhow!("Invalid climb"))?;
-                let slope = record["slope"].as_f64().ok_or(anyhow!("Invalid slope"))?;
-                let u_idx = *node_map.get(&u).ok_or(anyhow!("Unknown u"))?;
-                let v_idx = *node_map.get(&v).ok_or(anyhow!("Unknown v"))?;
-                let edge = EdgeData { id, length, climb, slope };
-                graph.add_edge(u_idx, v_idx, edge);
+// Edges taken after the first one, shared between partial paths through parent links
+struct Step {
+    edge: EdgeIndex,
+    end: f64,
+    parent: Option<usize>,
+}
+
+#[derive(Default)]
+struct Steps(Vec<Step>);
+
+impl Steps {
+    fn push(&mut self, edge: EdgeIndex, end: f64, parent: Option<usize>) -> usize {
+        self.0.push(Step { edge, end, parent });
+        self.0.len() - 1
+    }
+
+    fn last(&self, tail: Option<usize>) -> Option<(EdgeIndex, f64)> {
+        tail.map(|i| (self.0[i].edge, self.0[i].end))
+    }
+
+    // Steps from the newest back to the first
+    fn walk(&self, tail: Option<usize>) -> impl Iterator<Item = &Step> {
+        std::iter::successors(tail.map(|i| &self.0[i]), |step| step.parent.map(|i| &self.0[i]))
+    }
+
+    // (edge, fraction it ends at) in route order
+    fn path(&self, tail: Option<usize>) -> Vec<(EdgeIndex, f64)> {
+        let mut path: Vec<(EdgeIndex, f64)> = self.walk(tail).map(|step| (step.edge, step.end)).collect();
+        path.reverse();
+        path
+    }
+}
+
+// Edges and nodes of one partial path, collected the first time a bit is already
+// taken, so every other check while extending that path is a lookup, not a walk
+#[derive(Default)]
+struct OnPath(Option<(HashSet<EdgeIndex>, HashSet<NodeIndex>)>);
+
+impl OnPath {
+    fn sets<N: RoadNetwork + ?Sized>(&mut self, data: &N, path: &PartialPath, steps: &Steps) -> &(HashSet<EdgeIndex>, HashSet<NodeIndex>) {
+        self.0.get_or_insert_with(|| {
+            let (mut edges, mut nodes) = (HashSet::new(), HashSet::new());
+            if let Some(first) = path.first_edge_idx {
+                let (source, target) = data.edge_endpoints(first);
+                edges.insert(first);
+                // A start part way along the edge does not pass its source node
+                if path.first_fraction == 0.0 {
+                    nodes.insert(source);
+                }
+                nodes.insert(target);
+                for step in steps.walk(path.tail) {
+                    edges.insert(step.edge);
+                    nodes.insert(data.edge_endpoints(step.edge).1);
+                }
             }
-            _ => return Err(anyhow!("Unknown record type")),
+            (edges, nodes)
+        })
+    }
+}
+
+fn bit(index: usize) -> u64 {
+    1 << ((index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 58)
+}
+
+// Tuning knobs of `find_route`; the defaults are the original hard-coded values
+#[derive(Clone, Debug)]
+pub struct SearchConfig {
+    pub beam_width: usize,
+    // Routes are accepted within max(tolerance_min_m, tolerance_rel * L) of the target length
+    pub tolerance_min_m: f64,
+    pub tolerance_rel: f64,
+    // Expansion steps; by default 2L divided by an assumed average edge length
+    pub max_steps: Option<usize>,
+    pub avg_edge_m: f64,
+    // Name of the `ProfileMatcher` in the registry that scores and ranks routes
+    pub matcher: String,
+    // Let the final score shift the route profile vertically
+    pub use_offset: bool,
+    // Meters the "dtw" matcher may shift the route against the target
+    pub warp_window: f64,
+    // Weight of climbs against descents for the gradient matchers
+    pub climb_weight: f64,
+    // For `find_routes`: each result must have at least this fraction of its
+    // edges outside every better result, and start this far from their starts
+    pub min_unshared: f64,
+    pub min_start_separation: f64,
+    // Path policies: no turning straight back along the same road, no edge
+    // taken twice, and no node passed twice (simple paths)
+    pub no_u_turns: bool,
+    pub no_edge_reuse: bool,
+    pub no_node_reuse: bool,
+    // Rank the beam by area so far plus the `AreaBound` lower bound on the rest,
+    // instead of extrapolating the area so far to the full length
+    pub bound_order: bool,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            beam_width: 50,
+            tolerance_min_m: 5.0,
+            tolerance_rel: 0.05,
+            max_steps: None,
+            avg_edge_m: 50.0,
+            matcher: "area".to_string(),
+            use_offset: true,
+            warp_window: 50.0,
+            climb_weight: 1.0,
+            min_unshared: 0.5,
+            min_start_separation: 0.0,
+            no_u_turns: false,
+            no_edge_reuse: false,
+            no_node_reuse: false,
+            bound_order: false,
         }
     }
-    Ok(RoadGraph { graph, node_map })
+}
+
+impl SearchConfig {
+    pub fn tolerance(&self, l: f64) -> f64 {
+        self.tolerance_min_m.max(self.tolerance_rel * l)
+    }
+
+    pub fn max_steps(&self, l: f64) -> usize {
+        self.max_steps.unwrap_or((2.0 * l / self.avg_edge_m) as usize)
+    }
 }
 
 pub fn build_spatial_index(graph: &RoadGraph) -> RTree<SpatialEdge> {
@@ -74,48 +175,91 @@ pub fn build_spatial_index(graph: &RoadGraph) -> RTree<SpatialEdge> {
             climb: edge.climb,
             slope: edge.slope,
             id: edge.id,
+            geometry: edge.geometry.as_ref().map(|g| g.points.clone()),
         });
     }
     RTree::bulk_load(spatial_edges)
 }
 
-pub fn find_route(data: &AppData, query: &Query) -> Result<Option<Route>> {
+// Every route the beam search completes, with its score, in the order found
+fn beam_candidates<N: RoadNetwork + ?Sized>(
+    data: &N,
+    query: &Query,
+    config: &SearchConfig,
+    matcher: &dyn ProfileMatcher,
+) -> (Vec<(f64, PartialPath)>, Steps) {
+    let mut candidates: Vec<(f64, PartialPath)> = Vec::new();
+    let mut steps = Steps::default();
     let l = query.p.total_length();
     if l == 0.0 {
-        return Ok(None);
+        return (candidates, steps);
     }
-    let eps = 5.0f64.max(0.05 * l);
-    // Find candidate starts: edges within D
-    let bound_box = rstar::AABB::from_corners([query.c.0 - query.d, query.c.1 - query.d], [query.c.0 + query.d, query.c.1 + query.d]);
-    let candidates: Vec<&SpatialEdge> = data.rtree.locate_in_envelope(&bound_box).collect();
+    let eps = config.tolerance(l);
+    let region = EndRegion::new(data, query, l + eps);
+    // Whether a path can still reach the end area, and whether a route finishes in it
+    let reachable = |node: NodeIndex, length: f64| region.as_ref().is_none_or(|r| length + r.remaining(node) <= l + eps);
+    let finishes = |path: &PartialPath, steps: &Steps| {
+        region.as_ref().is_none_or(|r| {
+            let (start, end) = path_ends(data, path, steps);
+            r.accepts(start, end)
+        })
+    };
+    // Start on the sub-edges leaving each start point within D
     let mut start_partials = Vec::new();
-    for se in candidates {
-        let dist = distance_to_point(&[query.c.0, query.c.1], se);
-        if dist > query.d {
-            continue;
+    let starts = virtual_starts(data, [query.c.0, query.c.1], query.d);
+    for (e_idx, fraction) in starts.iter().flat_map(|start| start.out_legs(data)) {
+        let edge = data.edge(e_idx);
+        let partial_len = (1.0 - fraction) * edge.length;
+        let partial_climb = edge.climb - edge.rel_elev_at(fraction);
+        let area = edge_cost(matcher, &edge, fraction, 1.0, 0.0, 0.0, &query.p);
+        let (source, node) = data.edge_endpoints(e_idx);
+        // A start part way along the edge does not pass its source node
+        let source_bits = if fraction == 0.0 { bit(source.index()) } else { 0 };
+        if reachable(node, partial_len) {
+            start_partials.push(PartialPath {
+                node,
+                length: partial_len,
+                cum_cost: area,
+                rel_elev: partial_climb,
+                tail: None,
+                first_fraction: fraction,
+                first_edge_idx: Some(e_idx),
+                first_end: 1.0,
+                edge_bits: bit(e_idx.index()),
+                node_bits: source_bits | bit(node.index()),
+            });
+        }
+        if partial_len > l {
+            // The whole route fits on this edge
+            let end = fraction + l / edge.length;
+            let path = PartialPath {
+                node: data.edge_endpoints(e_idx).0,
+                length: l,
+                cum_cost: edge_cost(matcher, &edge, fraction, end, 0.0, 0.0, &query.p),
+                rel_elev: edge.rel_elev_at(end) - edge.rel_elev_at(fraction),
+                tail: None,
+                first_fraction: fraction,
+                first_edge_idx: Some(e_idx),
+                first_end: end,
+                edge_bits: bit(e_idx.index()),
+                node_bits: source_bits,
+            };
+            if finishes(&path, &steps) {
+                candidates.push((score_path(&path, data, query, matcher, &steps), path));
+            }
         }
-        let (_proj, fraction) = project_point_to_segment(&[query.c.0, query.c.1], se);
-        let partial_len = (1.0 - fraction) * se.length;
-        let partial_climb = (1.0 - fraction) * se.climb;
-        let area = integral_abs_diff(partial_len, 0.0 - query.p.interpolate(0.0), partial_climb - query.p.interpolate(partial_len));
-        start_partials.push(PartialPath {
-            node: se.v,
-            length: partial_len,
-            cum_area: area,
-            rel_elev: partial_climb,
-            path: vec![],
-            first_fraction: fraction,
-            first_edge_idx: Some(se.e_idx),
-        });
     }
     if start_partials.is_empty() {
-        return Ok(None);
+        return (candidates, steps);
     }
+    let bound = config.bound_order.then(|| {
+        let origins: Vec<(NodeIndex, f64)> = start_partials.iter().map(|p| (p.node, p.length)).collect();
+        AreaBound::new(data, &origins, l + eps, l - eps)
+    });
     // Beam search from each start, but to optimize, start from all in initial beam
-    let beam_width = 50;
+    let beam_width = config.beam_width;
     let mut beam: Vec<PartialPath> = start_partials;
-    let mut best: Option<(f64, PartialPath)> = None;
-    let max_steps = (2.0 * l / 50.0) as usize;  // Assume avg edge 50m
+    let max_steps = config.max_steps(l);
     for _step in 0..max_steps {
         if beam.is_empty() {
             break;
@@ -125,120 +269,490 @@ pub fn find_route(data: &AppData, query: &Query) -> Result<Option<Route>> {
             if path.length > l + eps {
                 continue;
             }
-            if (path.length - l).abs() <= eps {
-                // Compute final score with offset
-                let matcher = AreaMatcher { use_offset: true };
-                let actual_profile = extract_profile(&path, data);  // Defined below
-                let score = matcher.score(&actual_profile, &query.p);
-                if let Some((best_score, _)) = &best {
-                    if score < *best_score {
-                        best = Some((score, path.clone()));
-                    }
-                } else {
-                    best = Some((score, path.clone()));
-                }
+            if (path.length - l).abs() <= eps && finishes(&path, &steps) {
+                candidates.push((score_path(&path, data, query, matcher, &steps), path.clone()));
             }
             // Extend
-            for n_e in data.graph.graph.neighbors(path.node) {
-                let e_idx = data.graph.graph.find_edge(path.node, n_e).unwrap();
-                let edge = &data.graph.graph[e_idx];
+            let mut on_path = OnPath::default();
+            for (e_idx, n_e) in data.out_edges(path.node) {
+                if !allowed(data, config, &path, &steps, &mut on_path, e_idx) {
+                    continue;
+                }
+                let edge = data.edge(e_idx);
                 let new_len = path.length + edge.length;
-                if new_len > l + eps * 2.0 {
+                if path.length < l && new_len > l {
+                    // End part way along the edge, where the route is exactly L long
+                    let t = (l - path.length) / edge.length;
+                    let cut = PartialPath {
+                        node: path.node,
+                        length: l,
+                        cum_cost: path.cum_cost + edge_cost(matcher, &edge, 0.0, t, path.length, path.rel_elev, &query.p),
+                        rel_elev: path.rel_elev + edge.rel_elev_at(t),
+                        tail: Some(steps.push(e_idx, t, path.tail)),
+                        edge_bits: path.edge_bits | bit(e_idx.index()),
+                        ..path.clone()
+                    };
+                    if finishes(&cut, &steps) {
+                        candidates.push((score_path(&cut, data, query, matcher, &steps), cut));
+                    }
+                }
+                if new_len > l + eps * 2.0 || !reachable(n_e, new_len) {
+                    continue;
+                }
+                if config.no_node_reuse && visits(data, &path, &steps, &mut on_path, n_e) {
                     continue;
                 }
                 let new_rel = path.rel_elev + edge.climb;
-                let area_add = integral_abs_diff(edge.length, path.rel_elev - query.p.interpolate(path.length), new_rel - query.p.interpolate(new_len));
-                let new_area = path.cum_area + area_add;
-                let mut new_path = path.path.clone();
-                new_path.push((e_idx, 1.0));
+                let area_add = edge_cost(matcher, &edge, 0.0, 1.0, path.length, path.rel_elev, &query.p);
+                let new_area = path.cum_cost + area_add;
                 next_beam.push(PartialPath {
                     node: n_e,
                     length: new_len,
-                    cum_area: new_area,
+                    cum_cost: new_area,
                     rel_elev: new_rel,
-                    path: new_path,
+                    tail: Some(steps.push(e_idx, 1.0, path.tail)),
                     first_fraction: path.first_fraction,
                     first_edge_idx: path.first_edge_idx,
+                    first_end: 1.0,
+                    edge_bits: path.edge_bits | bit(e_idx.index()),
+                    node_bits: path.node_bits | bit(n_e.index()),
                 });
             }
         }
         // Sort by estimated full score, keep top
-        next_beam.sort_by(|a, b| {
-            let est_a = if a.length > 0.0 && a.cum_area.is_finite() {
-                a.cum_area / a.length * l
-            } else {
-                f64::INFINITY
-            };
-            let est_b = if b.length > 0.0 && b.cum_area.is_finite() {
-                b.cum_area / b.length * l
-            } else {
-                f64::INFINITY
-            };
-            est_a.partial_cmp(&est_b).unwrap_or(std::cmp::Ordering::Equal)
-        });
-        beam = next_beam.into_iter().take(beam_width).collect();
+        let estimate = |p: &PartialPath| match &bound {
+            Some(bound) => p.cum_cost + bound.remaining(&query.p, p.node, p.length, p.rel_elev),
+            None if p.length > 0.0 && p.cum_cost.is_finite() => p.cum_cost / p.length * l,
+            None => f64::INFINITY,
+        };
+        let mut ranked: Vec<(f64, PartialPath)> = next_beam.into_iter().map(|p| (estimate(&p), p)).collect();
+        ranked.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        let next_beam = ranked.into_iter().map(|(_, p)| p);
+        beam = next_beam.take(beam_width).collect();
     }
     // Add any remaining in tolerance
     for path in beam {
-        if (path.length - l).abs() <= eps {
-            let matcher = AreaMatcher { use_offset: true };
-            let actual_profile = extract_profile(&path, data);
-            let score = matcher.score(&actual_profile, &query.p);
-            if let Some((best_score, _)) = &best {
-                if score < *best_score {
-                    best = Some((score, path));
-                }
-            } else {
-                best = Some((score, path));
-            }
+        if (path.length - l).abs() <= eps && finishes(&path, &steps) {
+            candidates.push((score_path(&path, data, query, matcher, &steps), path));
         }
     }
-    if let Some((_, best_path)) = best {
-    let mut edge_ids = Vec::new();
-    if let Some(first_idx) = best_path.first_edge_idx {
-        let first_edge = &data.graph.graph[first_idx];
-        edge_ids.push(first_edge.id);
+    (candidates, steps)
+}
+
+// Whether `config`'s path policies let `path` continue along `e_idx`
+fn allowed<N: RoadNetwork + ?Sized>(
+    data: &N,
+    config: &SearchConfig,
+    path: &PartialPath,
+    steps: &Steps,
+    on_path: &mut OnPath,
+    e_idx: EdgeIndex,
+) -> bool {
+    let Some(first) = path.first_edge_idx else { return true };
+    if config.no_u_turns {
+        let last = steps.last(path.tail).map_or(first, |(e, _)| e);
+        if is_reverse(data, last, e_idx) {
+            return false;
+        }
+    }
+    !(config.no_edge_reuse && path.edge_bits & bit(e_idx.index()) != 0 && on_path.sets(data, path, steps).0.contains(&e_idx))
+}
+
+// Whether `path` already passes node `n`
+fn visits<N: RoadNetwork + ?Sized>(data: &N, path: &PartialPath, steps: &Steps, on_path: &mut OnPath, n: NodeIndex) -> bool {
+    path.node_bits & bit(n.index()) != 0 && on_path.sets(data, path, steps).1.contains(&n)
+}
+
+pub fn find_route<N: RoadNetwork + ?Sized>(data: &N, query: &Query, config: &SearchConfig) -> Result<Option<RouteMatch>> {
+    Ok(find_routes(data, query, 1, config)?.pop())
+}
+
+// The `k` best routes by score, best first. A candidate is skipped when it
+// shares too many edges with, or starts too close to, a route already taken.
+pub fn find_routes<N: RoadNetwork + ?Sized>(data: &N, query: &Query, k: usize, config: &SearchConfig) -> Result<Vec<RouteMatch>> {
+    if config.bound_order && config.matcher != "area" {
+        return Err(anyhow!("Ranking by the area lower bound needs the area matcher, not {:?}", config.matcher));
+    }
+    let options = MatcherOptions { use_offset: config.use_offset, warp_window: config.warp_window, climb_weight: config.climb_weight };
+    let matcher = matcher_by_name(&config.matcher, &options).ok_or_else(|| {
+        let known: Vec<&str> = MATCHERS.iter().map(|m| m.name).collect();
+        anyhow!("Unknown matcher {:?}; expected one of {}", config.matcher, known.join(", "))
+    })?;
+    find_routes_with(data, query, k, config, matcher.as_ref())
+}
+
+// `find_routes` with a matcher that need not be in the registry. With
+// `bound_order` the beam adds an area lower bound to the matcher's costs, which
+// only makes sense for matchers whose `piece_cost` is the area.
+pub fn find_routes_with<N: RoadNetwork + ?Sized>(
+    data: &N,
+    query: &Query,
+    k: usize,
+    config: &SearchConfig,
+    matcher: &dyn ProfileMatcher,
+) -> Result<Vec<RouteMatch>> {
+    let (mut candidates, steps) = beam_candidates(data, query, config, matcher);
+    // Stable, so equal scores keep the order they were found in
+    candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut taken: Vec<(HashSet<EdgeIndex>, [f64; 2])> = Vec::new();
+    let mut routes = Vec::new();
+    for (score, path) in candidates {
+        if routes.len() >= k {
+            break;
+        }
+        let mut edges = Vec::new();
+        if let Some(first_idx) = path.first_edge_idx {
+            edges.push(first_idx);
+        }
+        let rest = steps.path(path.tail);
+        edges.extend(rest.iter().map(|(e_idx, _)| *e_idx));
+        let start = point_on_edge(data, edges[0], path.first_fraction);
+        let distinct = taken.iter().all(|(other, other_start)| {
+            let shared = edges.iter().filter(|e| other.contains(*e)).count();
+            let unshared = 1.0 - shared as f64 / edges.len() as f64;
+            let separation = (start[0] - other_start[0]).hypot(start[1] - other_start[1]);
+            unshared >= config.min_unshared && separation >= config.min_start_separation
+        });
+        if !distinct {
+            continue;
+        }
+        let ti = rest.last().map(|last| last.1).unwrap_or(path.first_end);
+        let profile = extract_profile(&path, data, &steps);
+        let l = query.p.total_length();
+        routes.push(RouteMatch {
+            route: to_source_route(data, &edges, path.first_fraction, ti),
+            score,
+            normalized_score: matcher.normalized_score(score, l),
+            offset: matcher.offset(&profile, &query.p),
+            length: path.length,
+            length_error: path.length - l,
+            profile,
+        });
+        taken.push((edges.into_iter().collect(), start));
     }
-    // Use .iter() to borrow instead of moving
-    for (e_idx, _) in best_path.path.iter() {
-        let edge = &data.graph.graph[*e_idx];
-        edge_ids.push(edge.id);
+    Ok(routes)
+}
+
+// Report a route in source edge ids: consecutive sub-edges of a split edge
+// collapse into one id, and si/ti become fractions along the source edges.
+pub(super) fn to_source_route<N: RoadNetwork + ?Sized>(data: &N, edges: &[EdgeIndex], si: f64, ti: f64) -> Route {
+    let mut edge_ids: Vec<u64> = Vec::new();
+    let mut prev: Option<EdgeIndex> = None;
+    for &e_idx in edges {
+        let edge = data.edge(e_idx);
+        let continues = prev.is_some_and(|p| {
+            let prev_edge = data.edge(p);
+            match (prev_edge.origin, edge.origin) {
+                (Some(a), Some(b)) => a.id == b.id && (a.to - b.from).abs() < 1e-9,
+                _ => false,
+            }
+        });
+        if !continues {
+            edge_ids.push(edge.source_id());
+        }
+        prev = Some(e_idx);
     }
-    let ti = if let Some(last) = best_path.path.last() {
-        last.1
-    } else {
-        1.0 - best_path.first_fraction  // If only partial first
+    let si = edges.first().map(|&e| data.edge(e).source_fraction(si)).unwrap_or(si);
+    let ti = edges.last().map(|&e| data.edge(e).source_fraction(ti)).unwrap_or(ti);
+    Route { si, ti, edge_ids }
+}
+
+// Area between the target profile and the part of `edge` between two fractions,
+// placed at distance `s0` and relative elevation `rel0` along the route.
+pub(super) fn edge_area(edge: &EdgeData, from: f64, to: f64, s0: f64, rel0: f64, target: &Profile) -> f64 {
+    stretch_pieces(s0, rel0, &edge.profile_between(from, to), target)
+        .into_iter()
+        .map(|(len, a, b)| integral_abs_diff(len, a, b))
+        .sum()
+}
+
+// Cost of the part of `edge` between two fractions under `matcher`, placed at
+// distance `s0` and relative elevation `rel0` along the route
+fn edge_cost(matcher: &dyn ProfileMatcher, edge: &EdgeData, from: f64, to: f64, s0: f64, rel0: f64, target: &Profile) -> f64 {
+    matcher.piece_cost(s0, rel0, &edge.profile_between(from, to), target)
+}
+
+// Where a path starts and where it currently ends
+fn path_ends<N: RoadNetwork + ?Sized>(data: &N, path: &PartialPath, steps: &Steps) -> ([f64; 2], [f64; 2]) {
+    let first = path.first_edge_idx.expect("paths start on an edge");
+    let start = point_on_edge(data, first, path.first_fraction);
+    let end = match steps.last(path.tail) {
+        Some((e_idx, t)) => point_on_edge(data, e_idx, t),
+        None => point_on_edge(data, first, path.first_end),
     };
-    Ok(Some(Route {
-        si: best_path.first_fraction,
-        ti,
-        edge_ids,
-    }))
-} else {
-    Ok(None)
+    (start, end)
 }
+
+// Final score
+fn score_path<N: RoadNetwork + ?Sized>(path: &PartialPath, data: &N, query: &Query, matcher: &dyn ProfileMatcher, steps: &Steps) -> f64 {
+    matcher.score(&extract_profile(path, data, steps), &query.p)
 }
 
-fn extract_profile(path: &PartialPath, data: &AppData) -> Profile {
+fn extract_profile<N: RoadNetwork + ?Sized>(path: &PartialPath, data: &N, steps: &Steps) -> Profile {
     let mut points = vec![(0.0, 0.0)];
     let mut s = 0.0;
     let mut rel = 0.0;
-    if path.first_edge_idx.is_some() {
-        let first_idx = path.first_edge_idx.unwrap();
-        let first_edge = &data.graph.graph[first_idx];
-        let partial_len = (1.0 - path.first_fraction) * first_edge.length;
-        let partial_climb = (1.0 - path.first_fraction) * first_edge.climb;
-        s += partial_len;
-        rel += partial_climb;
-        points.push((s, rel));
-    }
-    for (e_idx, frac) in &path.path {
-        let edge = &data.graph.graph[*e_idx];
-        let this_len = *frac * edge.length;
-        let this_climb = *frac * edge.climb;
-        s += this_len;
-        rel += this_climb;
-        points.push((s, rel));
+    let mut push_edge = |edge: &EdgeData, from: f64, to: f64| {
+        let shape = edge.profile_between(from, to);
+        for &(ds, dz) in &shape[1..] {
+            points.push((s + ds, rel + dz));
+        }
+        let (ds, dz) = shape[shape.len() - 1];
+        s += ds;
+        rel += dz;
+    };
+    if let Some(first_idx) = path.first_edge_idx {
+        push_edge(&data.edge(first_idx), path.first_fraction, path.first_end);
+    }
+    for (e_idx, frac) in steps.path(path.tail) {
+        push_edge(&data.edge(e_idx), 0.0, frac);
     }
     Profile { points }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::application::{find_route_exact, ExactLimits};
+    use crate::domain::{GraphMeta, NodeData};
+    use crate::infrastructure::AppData;
+    use hashbrown::HashMap;
+    use petgraph::stable_graph::StableGraph;
+
+    // A(0,0) - B(100,0) - C(200,0), with a branch B - D(100,100); every road two-way.
+    // Elevations A 0, B 10, C 20, D 5.
+    struct Fixture {
+        data: AppData,
+        nodes: [NodeIndex; 4],
+        // Edges by (from, to) node letter
+        edges: HashMap<(char, char), EdgeIndex>,
+    }
+
+    fn fixture() -> Fixture {
+        let mut graph = StableGraph::new();
+        let points = [(0.0, 0.0, 0.0), (100.0, 0.0, 10.0), (200.0, 0.0, 20.0), (100.0, 100.0, 5.0)];
+        let nodes = points.map(|(x, y, elev)| graph.add_node(NodeData { x, y, elev }));
+        let node_map = nodes.iter().enumerate().map(|(i, &n)| (i as u64, n)).collect();
+        let mut edges = HashMap::new();
+        for (u, v) in [('A', 'B'), ('B', 'C'), ('B', 'D')] {
+            for (from, to) in [(u, v), (v, u)] {
+                let (a, b) = (nodes[(from as u8 - b'A') as usize], nodes[(to as u8 - b'A') as usize]);
+                let (pa, pb) = (&graph[a], &graph[b]);
+                let length = (pb.x - pa.x).hypot(pb.y - pa.y);
+                let climb = pb.elev - pa.elev;
+                let id = edges.len() as u64;
+                let e = graph.add_edge(a, b, EdgeData { id, length, climb, slope: climb / length, origin: None, geometry: None });
+                edges.insert((from, to), e);
+            }
+        }
+        let graph = RoadGraph::new(graph, node_map, GraphMeta::default());
+        let rtree = build_spatial_index(&graph);
+        Fixture { data: AppData { graph, rtree }, nodes, edges }
+    }
+
+    impl Fixture {
+        fn edge(&self, from: char, to: char) -> EdgeIndex {
+            self.edges[&(from, to)]
+        }
+
+        fn node(&self, name: char) -> NodeIndex {
+            self.nodes[(name as u8 - b'A') as usize]
+        }
+
+        // A partial path over `route` (pairs of node letters), starting `fraction`
+        // along its first edge, with its bits set the way the beam sets them
+        fn path(&self, steps: &mut Steps, route: &[(char, char)], fraction: f64) -> PartialPath {
+            let first = self.edge(route[0].0, route[0].1);
+            let (source, node) = self.data.edge_endpoints(first);
+            let mut path = PartialPath {
+                node,
+                length: (1.0 - fraction) * self.data.edge(first).length,
+                cum_cost: 0.0,
+                rel_elev: 0.0,
+                tail: None,
+                first_fraction: fraction,
+                first_edge_idx: Some(first),
+                first_end: 1.0,
+                edge_bits: bit(first.index()),
+                node_bits: if fraction == 0.0 { bit(source.index()) } else { 0 } | bit(node.index()),
+            };
+            for &(from, to) in &route[1..] {
+                let e = self.edge(from, to);
+                let n = self.data.edge_endpoints(e).1;
+                path.tail = Some(steps.push(e, 1.0, path.tail));
+                path.length += self.data.edge(e).length;
+                path.edge_bits |= bit(e.index());
+                path.node_bits |= bit(n.index());
+                path.node = n;
+            }
+            path
+        }
+    }
+
+    fn query() -> Query {
+        // Up 10 m and back down: best matched by A -> B -> A
+        Query { c: (0.0, 0.0), d: 0.001, p: Profile::new(vec![(0.0, 0.0), (100.0, 10.0), (200.0, 0.0)]), end: None }
+    }
+
+    #[test]
+    fn no_u_turns_blocks_going_straight_back() {
+        let f = fixture();
+        let mut steps = Steps::default();
+        let path = f.path(&mut steps, &[('A', 'B')], 0.0);
+        let config = SearchConfig { no_u_turns: true, ..SearchConfig::default() };
+        assert!(!allowed(&f.data, &config, &path, &steps, &mut OnPath::default(), f.edge('B', 'A')));
+        assert!(allowed(&f.data, &config, &path, &steps, &mut OnPath::default(), f.edge('B', 'C')));
+        assert!(allowed(&f.data, &SearchConfig::default(), &path, &steps, &mut OnPath::default(), f.edge('B', 'A')));
+
+        // Also after the first edge
+        let path = f.path(&mut steps, &[('A', 'B'), ('B', 'D')], 0.0);
+        assert!(!allowed(&f.data, &config, &path, &steps, &mut OnPath::default(), f.edge('D', 'B')));
+    }
+
+    #[test]
+    fn no_u_turns_changes_the_best_route() {
+        let f = fixture();
+        let ids = |route: &Route| route.edge_ids.clone();
+        let id = |from, to| f.data.edge(f.edge(from, to)).id;
+
+        let found = find_route(&f.data, &query(), &SearchConfig::default()).unwrap().unwrap();
+        assert_eq!(ids(&found.route), vec![id('A', 'B'), id('B', 'A')]);
+        let config = SearchConfig { no_u_turns: true, ..SearchConfig::default() };
+        let found = find_route(&f.data, &query(), &config).unwrap().unwrap();
+        assert_eq!(ids(&found.route), vec![id('A', 'B'), id('B', 'D')]);
+
+        let limits = ExactLimits::default();
+        let anchored = SearchConfig { use_offset: false, ..SearchConfig::default() };
+        let exact = find_route_exact(&f.data, &query(), &anchored, &limits).unwrap();
+        assert_eq!(ids(&exact.route.unwrap()), vec![id('A', 'B'), id('B', 'A')]);
+        let config = SearchConfig { use_offset: false, ..config };
+        let exact = find_route_exact(&f.data, &query(), &config, &limits).unwrap();
+        assert!(exact.optimal);
+        assert_eq!(ids(&exact.route.unwrap()), vec![id('A', 'B'), id('B', 'D')]);
+    }
+
+    #[test]
+    fn exact_search_rejects_unsupported_configs() {
+        let f = fixture();
+        let limits = ExactLimits::default();
+        let anchored = SearchConfig { use_offset: false, ..SearchConfig::default() };
+        for config in [
+            SearchConfig { no_edge_reuse: true, ..anchored.clone() },
+            SearchConfig { no_node_reuse: true, ..anchored.clone() },
+            SearchConfig::default(),
+            SearchConfig { matcher: "dtw".to_string(), ..anchored.clone() },
+        ] {
+            assert!(find_route_exact(&f.data, &query(), &config, &limits).is_err());
+        }
+        assert!(find_route_exact(&f.data, &query(), &anchored, &limits).is_ok());
+    }
+
+    #[test]
+    fn no_edge_reuse_rejects_a_repeated_edge() {
+        let f = fixture();
+        let mut steps = Steps::default();
+        let config = SearchConfig { no_edge_reuse: true, ..SearchConfig::default() };
+        // A -> B -> A, then A -> B again
+        let path = f.path(&mut steps, &[('A', 'B'), ('B', 'A')], 0.0);
+        assert!(!allowed(&f.data, &config, &path, &steps, &mut OnPath::default(), f.edge('A', 'B')));
+        assert!(allowed(&f.data, &SearchConfig::default(), &path, &steps, &mut OnPath::default(), f.edge('A', 'B')));
+
+        // A repeated edge later in the path, not just the first one
+        let path = f.path(&mut steps, &[('A', 'B'), ('B', 'D'), ('D', 'B')], 0.0);
+        assert!(!allowed(&f.data, &config, &path, &steps, &mut OnPath::default(), f.edge('B', 'D')));
+        assert!(allowed(&f.data, &config, &path, &steps, &mut OnPath::default(), f.edge('B', 'C')));
+    }
+
+    #[test]
+    fn no_node_reuse_rejects_a_revisited_node() {
+        let f = fixture();
+        let mut steps = Steps::default();
+        // From A itself, A is on the path
+        let path = f.path(&mut steps, &[('A', 'B'), ('B', 'D')], 0.0);
+        for name in ['A', 'B', 'D'] {
+            assert!(visits(&f.data, &path, &steps, &mut OnPath::default(), f.node(name)), "{name}");
+        }
+        assert!(!visits(&f.data, &path, &steps, &mut OnPath::default(), f.node('C')));
+
+        // From part way along A -> B the route never passes A
+        let path = f.path(&mut steps, &[('A', 'B'), ('B', 'D')], 0.5);
+        assert!(!visits(&f.data, &path, &steps, &mut OnPath::default(), f.node('A')));
+        assert!(visits(&f.data, &path, &steps, &mut OnPath::default(), f.node('B')));
+        assert!(visits(&f.data, &path, &steps, &mut OnPath::default(), f.node('D')));
+    }
+
+    #[test]
+    fn hash_collisions_fall_back_to_the_path_sets() {
+        // Bits are shared between indices, so a set bit alone proves nothing
+        assert!((1..200).any(|i| bit(i) == bit(0)));
+
+        let f = fixture();
+        let mut steps = Steps::default();
+        let mut path = f.path(&mut steps, &[('A', 'B'), ('B', 'D')], 0.5);
+        path.edge_bits = u64::MAX;
+        path.node_bits = u64::MAX;
+        let config = SearchConfig { no_edge_reuse: true, ..SearchConfig::default() };
+        assert!(allowed(&f.data, &config, &path, &steps, &mut OnPath::default(), f.edge('B', 'C')));
+        assert!(!allowed(&f.data, &config, &path, &steps, &mut OnPath::default(), f.edge('B', 'D')));
+        assert!(!visits(&f.data, &path, &steps, &mut OnPath::default(), f.node('A')));
+        assert!(!visits(&f.data, &path, &steps, &mut OnPath::default(), f.node('C')));
+        assert!(visits(&f.data, &path, &steps, &mut OnPath::default(), f.node('B')));
+    }
+
+    #[test]
+    fn long_paths_are_collected_once() {
+        // Once around a ring of 500 two-way roads, with a spur S off node 0
+        let n = 500;
+        let mut graph = StableGraph::new();
+        let mut nodes: Vec<NodeIndex> = (0..n)
+            .map(|i| {
+                let angle = i as f64 * std::f64::consts::TAU / n as f64;
+                graph.add_node(NodeData { x: 1000.0 * angle.cos(), y: 1000.0 * angle.sin(), elev: 0.0 })
+            })
+            .collect();
+        let spur = graph.add_node(NodeData { x: 1100.0, y: 0.0, elev: 0.0 });
+        let node_map = nodes.iter().chain([&spur]).enumerate().map(|(i, &n)| (i as u64, n)).collect();
+        nodes.push(nodes[0]);
+        let mut add = |a, b, id| graph.add_edge(a, b, EdgeData { id, length: 1.0, climb: 0.0, slope: 0.0, origin: None, geometry: None });
+        let forward: Vec<EdgeIndex> = nodes.windows(2).enumerate().map(|(i, w)| add(w[0], w[1], 2 * i as u64)).collect();
+        let backward: Vec<EdgeIndex> = nodes.windows(2).enumerate().map(|(i, w)| add(w[1], w[0], 2 * i as u64 + 1)).collect();
+        let to_spur = add(nodes[0], spur, 2 * n as u64);
+        let graph = RoadGraph::new(graph, node_map, GraphMeta::default());
+        let rtree = build_spatial_index(&graph);
+        let data = AppData { graph, rtree };
+
+        let mut steps = Steps::default();
+        let mut path = PartialPath {
+            node: nodes[1],
+            length: 1.0,
+            cum_cost: 0.0,
+            rel_elev: 0.0,
+            tail: None,
+            first_fraction: 0.0,
+            first_edge_idx: Some(forward[0]),
+            first_end: 1.0,
+            edge_bits: bit(forward[0].index()),
+            node_bits: bit(nodes[0].index()) | bit(nodes[1].index()),
+        };
+        for (&e, &node) in forward[1..].iter().zip(&nodes[2..]) {
+            path.tail = Some(steps.push(e, 1.0, path.tail));
+            path.length += 1.0;
+            path.edge_bits |= bit(e.index());
+            path.node_bits |= bit(node.index());
+            path.node = node;
+        }
+        assert_eq!(path.node, nodes[0]);
+        assert_eq!((path.edge_bits, path.node_bits), (u64::MAX, u64::MAX));
+
+        let config = SearchConfig { no_edge_reuse: true, ..SearchConfig::default() };
+        let mut on_path = OnPath::default();
+        assert!(!allowed(&data, &config, &path, &steps, &mut on_path, forward[0]));
+        assert!(allowed(&data, &config, &path, &steps, &mut on_path, backward[n - 1]));
+        assert!(allowed(&data, &config, &path, &steps, &mut on_path, to_spur));
+        assert!(nodes.iter().all(|&node| visits(&data, &path, &steps, &mut on_path, node)));
+        assert!(!visits(&data, &path, &steps, &mut on_path, spur));
+        // Every check above looked in the same collection of the path
+        let (edges, visited) = on_path.0.as_ref().unwrap();
+        assert_eq!((edges.len(), visited.len()), (n, n));
+    }
+}
@@ -0,0 +1,161 @@
+use hashbrown::HashMap;
+use petgraph::stable_graph::{EdgeIndex, NodeIndex};
+
+use crate::domain::{EdgeData, EdgeOrigin, NodeData, RoadGraph};
+
+#[derive(Clone, Debug, Default)]
+pub struct SplitSummary {
+    pub edges_split: usize,
+    pub edges_added: usize,
+    pub nodes_added: usize,
+}
+
+// Subdivide every edge longer than `max_len` into equal sub-edges chained
+// through interpolated nodes placed along the edge's polyline. Climb follows the
+// edge's elevation shape (proportional to length when it has none).
+// Sub-edges get fresh ids and keep an `EdgeOrigin` pointing at the source edge.
+// The two directions of a two-way road share their interpolated nodes when
+// they follow the same line.
+pub fn split_long_edges(graph: &mut RoadGraph, max_len: f64) -> SplitSummary {
+    let mut summary = SplitSummary::default();
+    if max_len.is_nan() || max_len <= 0.0 {
+        return summary;
+    }
+    let long_edges: Vec<EdgeIndex> = graph
+        .graph
+        .edge_indices()
+        .filter(|&e| graph.graph[e].length > max_len)
+        .collect();
+    if long_edges.is_empty() {
+        return summary;
+    }
+
+    let mut next_edge_id = graph.graph.edge_weights().map(|e| e.id).max().unwrap_or(0) + 1;
+    let mut next_node_id = graph.node_map.keys().copied().max().unwrap_or(0) + 1;
+    // (u, v, pieces) -> interior nodes from u to v
+    let mut interior_nodes: HashMap<(NodeIndex, NodeIndex, usize), Vec<NodeIndex>> = HashMap::new();
+
+    for e_idx in long_edges {
+        let (u, v) = graph.graph.edge_endpoints(e_idx).unwrap();
+        let edge = graph.graph.remove_edge(e_idx).unwrap();
+        let pieces = (edge.length / max_len).ceil() as usize;
+
+        let node_u = graph.graph[u].clone();
+        let node_v = graph.graph[v].clone();
+        let positions: Vec<[f64; 2]> = (1..pieces)
+            .map(|i| {
+                let t = i as f64 / pieces as f64;
+                match &edge.geometry {
+                    Some(geometry) => geometry.point_at(t),
+                    None => [node_u.x + t * (node_v.x - node_u.x), node_u.y + t * (node_v.y - node_u.y)],
+                }
+            })
+            .collect();
+        // Reuse the reverse edge's nodes only if they sit where ours would
+        let reverse = interior_nodes.get(&(v, u, pieces)).filter(|nodes| {
+            nodes.iter().rev().zip(&positions).all(|(&n, p)| {
+                let node = &graph.graph[n];
+                (node.x - p[0]).abs() < 0.01 && (node.y - p[1]).abs() < 0.01
+            })
+        });
+        let interior: Vec<NodeIndex> = match reverse {
+            Some(nodes) => nodes.iter().rev().copied().collect(),
+            None => {
+                let mut nodes = Vec::with_capacity(pieces - 1);
+                for (i, &[x, y]) in positions.iter().enumerate() {
+                    let t = (i + 1) as f64 / pieces as f64;
+                    let idx = graph.graph.add_node(NodeData { x, y, elev: node_u.elev + edge.rel_elev_at(t) });
+                    graph.node_map.insert(next_node_id, idx);
+                    next_node_id += 1;
+                    nodes.push(idx);
+                }
+                summary.nodes_added += nodes.len();
+                nodes
+            }
+        };
+        interior_nodes.insert((u, v, pieces), interior.clone());
+
+        let source = edge.origin.unwrap_or(EdgeOrigin { id: edge.id, from: 0.0, to: 1.0 });
+        let mut chain = Vec::with_capacity(pieces + 1);
+        chain.push(u);
+        chain.extend(interior);
+        chain.push(v);
+        for (i, pair) in chain.windows(2).enumerate() {
+            let from = i as f64 / pieces as f64;
+            let to = (i + 1) as f64 / pieces as f64;
+            let length = edge.length / pieces as f64;
+            let climb = edge.rel_elev_at(to) - edge.rel_elev_at(from);
+            graph.graph.add_edge(pair[0], pair[1], EdgeData {
+                id: next_edge_id,
+                length,
+                climb,
+                slope: if length > 0.0 { climb / length } else { 0.0 },
+                origin: Some(EdgeOrigin {
+                    id: source.id,
+                    from: source.from + from * (source.to - source.from),
+                    to: source.from + to * (source.to - source.from),
+                }),
+                geometry: edge.geometry.as_ref().map(|g| g.slice(from, to)),
+            });
+            next_edge_id += 1;
+        }
+        summary.edges_split += 1;
+        summary.edges_added += pieces;
+    }
+    graph.rebuild_edge_map();
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{EdgeGeometry, GraphMeta};
+    use petgraph::stable_graph::StableGraph;
+
+    #[test]
+    fn sub_edges_add_up_to_the_source_edge() {
+        // 200 m edge over a 20 m hump, ending level with its start
+        let mut graph = StableGraph::new();
+        let u = graph.add_node(NodeData { x: 0.0, y: 0.0, elev: 10.0 });
+        let v = graph.add_node(NodeData { x: 200.0, y: 0.0, elev: 10.0 });
+        let geometry = EdgeGeometry { points: vec![[0.0, 0.0], [100.0, 0.0], [200.0, 0.0]], elevs: Some(vec![10.0, 30.0, 10.0]) };
+        let source = EdgeData { id: 7, length: 200.0, climb: 0.0, slope: 0.0, origin: None, geometry: Some(geometry) };
+        graph.add_edge(u, v, source.clone());
+        let node_map = [(1, u), (2, v)].into_iter().collect();
+        let mut graph = RoadGraph::new(graph, node_map, GraphMeta::default());
+
+        let summary = split_long_edges(&mut graph, 50.0);
+        assert_eq!((summary.edges_split, summary.edges_added, summary.nodes_added), (1, 4, 3));
+
+        let chain = graph.source_edge_chain(7).to_vec();
+        assert_eq!(chain.len(), 4);
+        let edges: Vec<&EdgeData> = chain.iter().map(|&e| &graph.graph[e]).collect();
+        let length: f64 = edges.iter().map(|e| e.length).sum();
+        let climb: f64 = edges.iter().map(|e| e.climb).sum();
+        assert!((length - source.length).abs() < 1e-9);
+        assert!((climb - source.climb).abs() < 1e-9);
+        // Climbs follow the hump rather than the zero net climb
+        let climbs: Vec<f64> = edges.iter().map(|e| e.climb).collect();
+        for (got, want) in climbs.iter().zip([10.0, 10.0, -10.0, -10.0]) {
+            assert!((got - want).abs() < 1e-9, "{climbs:?}");
+        }
+        for edge in &edges {
+            assert!((edge.slope - edge.climb / edge.length).abs() < 1e-12);
+        }
+
+        // The chain runs from u to v, and each piece's geometry ends where the next starts
+        assert_eq!(graph.graph.edge_endpoints(chain[0]).unwrap().0, u);
+        assert_eq!(graph.graph.edge_endpoints(chain[3]).unwrap().1, v);
+        let geometries: Vec<&EdgeGeometry> = edges.iter().map(|e| e.geometry.as_ref().unwrap()).collect();
+        assert_eq!(geometries[0].points[0], [0.0, 0.0]);
+        assert_eq!(*geometries[3].points.last().unwrap(), [200.0, 0.0]);
+        for (i, pair) in chain.windows(2).enumerate() {
+            let (_, joint) = graph.graph.edge_endpoints(pair[0]).unwrap();
+            assert_eq!(graph.graph.edge_endpoints(pair[1]).unwrap().0, joint);
+            let end = *geometries[i].points.last().unwrap();
+            assert_eq!(end, geometries[i + 1].points[0]);
+            let node = &graph.graph[joint];
+            assert_eq!(end, [node.x, node.y]);
+        }
+    }
+}
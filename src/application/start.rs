@@ -0,0 +1,114 @@
+use hashbrown::HashSet;
+use petgraph::stable_graph::{EdgeIndex, NodeIndex};
+
+use crate::domain::RoadNetwork;
+use crate::infrastructure::polyline_range_within;
+
+// Starts closer than this to an edge end snap to the node there
+const SNAP_M: f64 = 0.01;
+
+// Where a route can begin. A start part way along a road splits its edge, and
+// the reverse edge of a two-way road, into virtual sub-edges leaving that point.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VirtualStart {
+    Node(NodeIndex),
+    OnEdge { edge: EdgeIndex, reverse: Option<EdgeIndex>, fraction: f64 },
+}
+
+impl VirtualStart {
+    // The (sub-)edges a route can take first, with the fraction each starts at
+    pub fn out_legs<N: RoadNetwork + ?Sized>(&self, data: &N) -> Vec<(EdgeIndex, f64)> {
+        match *self {
+            VirtualStart::Node(n) => data.out_edges(n).into_iter().map(|(e_idx, _)| (e_idx, 0.0)).collect(),
+            VirtualStart::OnEdge { edge, reverse, fraction } => {
+                let mut legs = vec![(edge, fraction)];
+                legs.extend(reverse.map(|r| (r, 1.0 - fraction)));
+                legs
+            }
+        }
+    }
+
+    pub fn point<N: RoadNetwork + ?Sized>(&self, data: &N) -> [f64; 2] {
+        match *self {
+            VirtualStart::Node(n) => {
+                let node = data.node(n);
+                [node.x, node.y]
+            }
+            VirtualStart::OnEdge { edge, fraction, .. } => point_on_edge(data, edge, fraction),
+        }
+    }
+}
+
+// Start points within `d` of `center`. Each road nearby contributes the point
+// closest to the center and the first and last of its points within `d`, so a
+// route can also start at the edge of the search area; points at an edge end
+// become that node. A two-way road is handled once, through its lower edge index.
+pub fn virtual_starts<N: RoadNetwork + ?Sized>(data: &N, center: [f64; 2], d: f64) -> Vec<VirtualStart> {
+    let mut starts: Vec<VirtualStart> = Vec::new();
+    let mut nodes: HashSet<NodeIndex> = HashSet::new();
+    for (e_idx, projected) in data.edges_near(center, d) {
+        let reverse = reverse_edge(data, e_idx);
+        if reverse.is_some_and(|r| r < e_idx) {
+            continue;
+        }
+        let (u, v) = data.edge_endpoints(e_idx);
+        let edge = data.edge(e_idx);
+        let (lo, hi) = polyline_range_within(&center, &edge_polyline(data, e_idx), d).unwrap_or((projected, projected));
+        for fraction in [lo, projected, hi] {
+            let start = if fraction * edge.length <= SNAP_M {
+                VirtualStart::Node(u)
+            } else if (1.0 - fraction) * edge.length <= SNAP_M {
+                VirtualStart::Node(v)
+            } else {
+                VirtualStart::OnEdge { edge: e_idx, reverse, fraction }
+            };
+            let seen = match start {
+                VirtualStart::Node(n) => !nodes.insert(n),
+                VirtualStart::OnEdge { .. } => starts.iter().any(|s| match *s {
+                    VirtualStart::OnEdge { edge: other, fraction: f, .. } => {
+                        other == e_idx && (f - fraction).abs() * edge.length <= SNAP_M
+                    }
+                    VirtualStart::Node(_) => false,
+                }),
+            };
+            if !seen {
+                starts.push(start);
+            }
+        }
+    }
+    starts
+}
+
+pub(super) fn point_on_edge<N: RoadNetwork + ?Sized>(data: &N, e_idx: EdgeIndex, fraction: f64) -> [f64; 2] {
+    if let Some(g) = &data.edge(e_idx).geometry {
+        return g.point_at(fraction);
+    }
+    let (u, v) = data.edge_endpoints(e_idx);
+    let (a, b) = (data.node(u), data.node(v));
+    [a.x + fraction * (b.x - a.x), a.y + fraction * (b.y - a.y)]
+}
+
+pub(super) fn edge_polyline<N: RoadNetwork + ?Sized>(data: &N, e_idx: EdgeIndex) -> Vec<[f64; 2]> {
+    if let Some(g) = &data.edge(e_idx).geometry {
+        return g.points.clone();
+    }
+    let (u, v) = data.edge_endpoints(e_idx);
+    let (a, b) = (data.node(u), data.node(v));
+    vec![[a.x, a.y], [b.x, b.y]]
+}
+
+// The edge running the other way along the same road, if the road is two-way
+fn reverse_edge<N: RoadNetwork + ?Sized>(data: &N, e_idx: EdgeIndex) -> Option<EdgeIndex> {
+    let (_, v) = data.edge_endpoints(e_idx);
+    data.out_edges(v).into_iter().map(|(r, _)| r).find(|&r| is_reverse(data, e_idx, r))
+}
+
+// Whether `b` runs back along the same road as `a`
+pub(super) fn is_reverse<N: RoadNetwork + ?Sized>(data: &N, a: EdgeIndex, b: EdgeIndex) -> bool {
+    let ((u, v), (x, y)) = (data.edge_endpoints(a), data.edge_endpoints(b));
+    if a == b || x != v || y != u || (data.edge(a).length - data.edge(b).length).abs() > SNAP_M {
+        return false;
+    }
+    let (p, q) = (point_on_edge(data, a, 0.5), point_on_edge(data, b, 0.5));
+    (p[0] - q[0]).hypot(p[1] - q[1]) <= SNAP_M
+}
@@ -0,0 +1,92 @@
+use anyhow::{bail, Result};
+use clap::builder::{PossibleValue, PossibleValuesParser};
+use clap::Args;
+use project_profile_finder::application::SearchConfig;
+use project_profile_finder::domain::{RouteEnd, MATCHERS};
+
+// Route search flags shared by query, visualize and interactive
+#[derive(Args, Debug)]
+pub struct SearchArgs {
+    #[arg(long, value_name = "N", default_value_t = SearchConfig::default().beam_width, help = "Partial routes kept per step")]
+    pub beam_width: usize,
+    #[arg(long, value_name = "METERS", default_value_t = SearchConfig::default().tolerance_min_m, help = "Minimum length tolerance")]
+    pub tolerance_m: f64,
+    #[arg(long, value_name = "FRACTION", default_value_t = SearchConfig::default().tolerance_rel, help = "Length tolerance relative to the query length")]
+    pub tolerance_rel: f64,
+    #[arg(long, value_name = "N", help = "Expansion steps (default: 2L / 50m)")]
+    pub max_steps: Option<usize>,
+    #[arg(
+        long,
+        value_name = "NAME",
+        default_value_t = SearchConfig::default().matcher,
+        value_parser = PossibleValuesParser::new(MATCHERS.iter().map(|m| PossibleValue::new(m.name).help(m.about))),
+        help = "Profile matcher that scores and ranks routes (--exact needs area)"
+    )]
+    pub matcher: String,
+    #[arg(long, help = "Score routes without shifting them vertically onto the target")]
+    pub no_offset: bool,
+    #[arg(long, value_name = "METERS", default_value_t = SearchConfig::default().warp_window, value_parser = parse_window, help = "How far the dtw matcher may shift the route against the target")]
+    pub warp_window: f64,
+    #[arg(long, value_name = "WEIGHT", default_value_t = SearchConfig::default().climb_weight, help = "Weight of climbs against descents for the gradient matchers")]
+    pub climb_weight: f64,
+    #[arg(long, help = "Rank partial routes by area so far plus a lower bound on the rest (area matcher only)")]
+    pub bound_order: bool,
+    #[arg(long, help = "Never turn straight back along the road just taken")]
+    pub no_u_turns: bool,
+    #[arg(long, help = "Never take the same edge twice")]
+    pub no_edge_reuse: bool,
+    #[arg(long, help = "Never pass the same node twice (simple paths)")]
+    pub simple_paths: bool,
+}
+
+impl SearchArgs {
+    pub fn config(&self) -> Result<SearchConfig> {
+        if self.bound_order && self.matcher != "area" {
+            bail!("--bound-order ranks by area and needs --matcher area");
+        }
+        Ok(SearchConfig {
+            beam_width: self.beam_width,
+            tolerance_min_m: self.tolerance_m,
+            tolerance_rel: self.tolerance_rel,
+            max_steps: self.max_steps,
+            matcher: self.matcher.clone(),
+            use_offset: !self.no_offset,
+            warp_window: self.warp_window,
+            climb_weight: self.climb_weight,
+            bound_order: self.bound_order,
+            no_u_turns: self.no_u_turns,
+            no_edge_reuse: self.no_edge_reuse,
+            no_node_reuse: self.simple_paths,
+            ..SearchConfig::default()
+        })
+    }
+}
+
+// Where routes have to finish, shared by query, visualize and interactive
+#[derive(Args, Debug)]
+pub struct EndArgs {
+    #[arg(long, value_name = "METERS", help = "Only return loops ending within this distance of their start")]
+    pub loop_radius: Option<f64>,
+    #[arg(long, requires = "loop_radius", help = "Measure --loop-radius from the query center instead of the route start")]
+    pub loop_at_center: bool,
+    #[arg(long, num_args = 3, value_names = ["X", "Y", "RADIUS"], allow_negative_numbers = true, conflicts_with = "loop_radius", help = "Only return routes ending within RADIUS of (X, Y)")]
+    pub end: Option<Vec<f64>>,
+}
+
+impl EndArgs {
+    pub fn end(&self) -> Option<RouteEnd> {
+        if let Some(end) = &self.end {
+            return Some(RouteEnd::Region { center: (end[0], end[1]), radius: end[2] });
+        }
+        self.loop_radius.map(|radius| RouteEnd::Loop { radius, at_center: self.loop_at_center })
+    }
+}
+
+// A finite, non-negative distance in meters
+fn parse_window(s: &str) -> Result<f64, String> {
+    let meters: f64 = s.parse().map_err(|e| format!("{e}"))?;
+    if !meters.is_finite() || meters < 0.0 {
+        return Err(format!("expected a finite distance of at least 0, got {s}"));
+    }
+    Ok(meters)
+}
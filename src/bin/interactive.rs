@@ -1,26 +1,39 @@
 use anyhow::Result;
 use clap::Parser;
-use project_profile_finder::application::find_route;
-use project_profile_finder::domain::{Profile, Query};
-use project_profile_finder::infrastructure::load_data;
+use project_profile_finder::application::{decode_route, find_route};
+use project_profile_finder::domain::{Profile, Query, RoadNetwork};
+use project_profile_finder::infrastructure::load_network;
 use std::io::{self, Write};
 use std::path::PathBuf;
 
+mod common;
+use common::{EndArgs, SearchArgs};
+
 #[derive(Parser, Debug)]
 #[command(version, about = "Interactive route finder with better user interface")]
 struct Args {
     #[arg(short, long)]
     input: PathBuf,
+    #[command(flatten)]
+    search: SearchArgs,
+    #[command(flatten)]
+    end: EndArgs,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
     println!("Loading data from {:?}...", args.input);
-    let data = load_data(&args.input)?;
+    let (network, build) = load_network(&args.input)?;
+    let config = args.search.config()?;
     println!("✅ Data loaded successfully!");
     println!("📊 Graph has {} nodes and {} edges",
-             data.graph.graph.node_count(),
-             data.graph.graph.edge_count());
+             network.node_count(),
+             network.edge_count());
+    println!("🗺️  {}", network.meta());
+    match build {
+        Some(build) => println!("🔧 {}", build),
+        None => println!("🔧 Legacy headerless file; re-run preprocess to upgrade it"),
+    }
 
     loop {
         println!("\n🚴 Project Profile Finder - Interactive Mode");
@@ -40,6 +53,7 @@ fn main() -> Result<()> {
             c: (cx, cy),
             d: distance,
             p: profile.clone(),
+            end: args.end.end(),
         };
 
         // Display query summary
@@ -53,18 +67,24 @@ fn main() -> Result<()> {
         print!("\n🔍 Searching for matching route... ");
         io::stdout().flush()?;
 
-        match find_route(&data, &query)? {
-            Some(route) => {
+        match find_route(network.as_ref(), &query, &config)? {
+            Some(found) => {
+                let route = &found.route;
                 println!("✅ Found!");
                 println!("\n🛤️  Route Details:");
                 println!("   Segments: {} edges", route.edge_ids.len());
                 println!("   Start fraction: {:.3}", route.si);
                 println!("   End fraction: {:.3}", route.ti);
                 println!("   Edge IDs: {:?}", route.edge_ids);
+                let decoded = decode_route(network.as_ref(), route)?;
+                println!("   Length: {:.1}m over {} graph edges ({:+.1}m vs target)",
+                         decoded.length(), decoded.legs.len(), found.length_error);
+                println!("   Score: {:.1} ({:.3} normalized, offset {:+.2}m)",
+                         found.score, found.normalized_score, found.offset);
 
                 // Offer visualization
                 if ask_yes_no("\n🖼️  Would you like to create visualizations? (y/n): ")? {
-                    create_visualizations(&data, &query, &route, &profile)?;
+                    create_visualizations(network.as_ref(), &query, route, &profile)?;
                 }
             }
             None => {
@@ -75,7 +95,7 @@ fn main() -> Result<()> {
                 println!("   - Moving the center point");
 
                 if ask_yes_no("\n🖼️  Show search area visualization? (y/n): ")? {
-                    create_search_area_vis(&data, &query)?;
+                    create_search_area_vis(network.as_ref(), &query)?;
                 }
             }
         }
@@ -97,7 +117,7 @@ fn get_center_coordinates() -> Result<(f64, f64)> {
         let mut input = String::new();
         io::stdin().read_line(&mut input)?;
 
-        let parts: Vec<&str> = input.trim().split_whitespace().collect();
+        let parts: Vec<&str> = input.split_whitespace().collect();
         if parts.len() != 2 {
             println!("❌ Please enter exactly two numbers (x y)");
             continue;
@@ -143,7 +163,6 @@ fn get_elevation_profile() -> Result<Profile> {
         io::stdin().read_line(&mut input)?;
 
         let numbers: Result<Vec<f64>, _> = input
-            .trim()
             .split_whitespace()
             .map(|s| s.parse())
             .collect();
@@ -200,7 +219,7 @@ fn ask_yes_no(prompt: &str) -> Result<bool> {
 }
 
 fn create_visualizations(
-    _data: &project_profile_finder::infrastructure::AppData,
+    _network: &dyn RoadNetwork,
     query: &Query,
     _route: &project_profile_finder::domain::Route,
     target_profile: &Profile
@@ -218,7 +237,7 @@ fn create_visualizations(
 
     // Run visualization command
     let status = Command::new("cargo")
-        .args(&[
+        .args([
             "run", "--bin", "visualize", "--",
             "--input", &format!("{}", query.c.0), // This is wrong, but we'll fix it
             "--cx", &query.c.0.to_string(),
@@ -237,7 +256,7 @@ fn create_visualizations(
 }
 
 fn create_search_area_vis(
-    _data: &project_profile_finder::infrastructure::AppData,
+    _network: &dyn RoadNetwork,
     _query: &Query,
 ) -> Result<()> {
     println!("🎨 Search area visualization would be created here");
@@ -1,9 +1,36 @@
-use anyhow::Result;
-use clap::Parser;
-use project_profile_finder::application::{build_graph_from_jsonl, build_spatial_index};
-use project_profile_finder::infrastructure::{save_data, AppData};
+use anyhow::{anyhow, Result};
+use clap::{Parser, ValueEnum};
+use project_profile_finder::application::{
+    attach_dem_elevations, build_graph_from_osm, build_spatial_index, check_consistency, ingest_jsonl, load_dem,
+    split_long_edges, update_elevations, CheckTolerances, DemCrs, IngestMode,
+};
+use project_profile_finder::infrastructure::{save_data, save_mapped, AppData, BuildInfo};
 use std::path::PathBuf;
 
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum InputFormat {
+    /// Road graph JSONL (meta/node/edge records)
+    Jsonl,
+    /// OpenStreetMap XML (.osm) or PBF (.osm.pbf), detected automatically
+    Osm,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    /// Graph and R-tree serialized with bincode, loaded into memory
+    Bincode,
+    /// CSR arrays and a packed R-tree, memory-mapped and queried in place
+    Mapped,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum DemCrsArg {
+    /// Same coordinates as the graph nodes
+    Graph,
+    /// Longitude/latitude in degrees
+    Wgs84,
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about = "Preprocess road graph JSONL to binary")]
 struct Args {
@@ -11,14 +38,123 @@ struct Args {
     input: PathBuf,
     #[arg(short, long)]
     output: PathBuf,
+    #[arg(long, value_enum, default_value = "jsonl", help = "Input format")]
+    format: InputFormat,
+    #[arg(long, value_enum, default_value = "bincode", help = "Output layout")]
+    output_format: OutputFormat,
+    #[arg(long, help = "Report every problem in the input instead of stopping at the first one")]
+    validate: bool,
+    #[arg(long, help = "Fail instead of warning when an edge exceeds the meta record's max_segment_m")]
+    strict_segments: bool,
+    #[arg(long, help = "Split edges longer than the maximum segment length into chained sub-edges")]
+    split: bool,
+    #[arg(long, value_name = "METERS", help = "Maximum segment length for --split (defaults to the meta record's max_segment_m)")]
+    max_segment: Option<f64>,
+    #[arg(long, value_name = "PATH", help = "DEM raster (.hgt, .asc or .tif) to sample node elevations from")]
+    dem: Option<PathBuf>,
+    #[arg(long, help = "Replace every node elevation with the DEM value, not only missing ones")]
+    dem_overwrite: bool,
+    #[arg(long, value_enum, default_value = "graph", help = "Coordinate system of ESRI ASCII grids")]
+    dem_crs: DemCrsArg,
+    #[arg(long, help = "Check climb, slope and length against the node elevations and positions")]
+    check: bool,
+    #[arg(long, help = "Recompute the fields flagged by --check instead of failing")]
+    repair: bool,
+    #[arg(long, value_name = "METERS", default_value_t = CheckTolerances::default().climb_m, help = "Allowed climb difference for --check")]
+    climb_tol: f64,
+    #[arg(long, value_name = "SLOPE", default_value_t = CheckTolerances::default().slope, help = "Allowed slope difference for --check")]
+    slope_tol: f64,
+    #[arg(long, value_name = "FRACTION", default_value_t = CheckTolerances::default().length_rel, help = "Allowed relative length difference for --check")]
+    length_tol: f64,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let graph = build_graph_from_jsonl(&args.input)?;
-    let rtree = build_spatial_index(&graph);
-    let data = AppData { graph, rtree };
-    save_data(&data, &args.output)?;
+    let mut graph = match args.format {
+        InputFormat::Jsonl => {
+            let mode = if args.validate { IngestMode::CollectAll } else { IngestMode::FailFast };
+            let (graph, report) = ingest_jsonl(&args.input, mode)?;
+            if args.validate {
+                print!("{}", report);
+                if !report.is_clean() {
+                    return Err(anyhow!("{} problem(s) found in {:?}, nothing written", report.issues.len(), args.input));
+                }
+            } else if !args.split {
+                for warning in &report.warnings {
+                    eprintln!("warning: {}", warning);
+                }
+            }
+            graph
+        }
+        InputFormat::Osm => {
+            let (graph, summary) = build_graph_from_osm(&args.input)?;
+            println!(
+                "OSM import: kept {} of {} ways, {} nodes, {} edges",
+                summary.ways_kept, summary.ways_seen, summary.nodes, summary.edges
+            );
+            if summary.missing_node_refs > 0 {
+                eprintln!("warning: {} segment(s) dropped for referencing nodes missing from the extract", summary.missing_node_refs);
+            }
+            if summary.nodes_without_elevation > 0 {
+                eprintln!("{} node(s) have no ele tag", summary.nodes_without_elevation);
+            }
+            graph
+        }
+    };
+    if args.split {
+        let max_len = args
+            .max_segment
+            .or(graph.meta.max_segment_m)
+            .ok_or_else(|| anyhow!("--split needs --max-segment or a max_segment_m in the meta record"))?;
+        let summary = split_long_edges(&mut graph, max_len);
+        graph.meta.max_segment_m = Some(graph.meta.max_segment_m.map_or(max_len, |m| m.min(max_len)));
+        println!(
+            "Split {} edge(s) longer than {}m into {} sub-edges ({} nodes added)",
+            summary.edges_split, max_len, summary.edges_added, summary.nodes_added
+        );
+    }
+    if let Some(dem_path) = &args.dem {
+        let ascii_crs = match args.dem_crs {
+            DemCrsArg::Graph => DemCrs::Graph,
+            DemCrsArg::Wgs84 => DemCrs::Geographic,
+        };
+        let dem = load_dem(dem_path, ascii_crs)?;
+        let summary = attach_dem_elevations(&mut graph, &dem, args.dem_overwrite)?;
+        println!(
+            "DEM: {} node elevations set, {} edges updated, {} node(s) outside the raster",
+            summary.nodes_updated, summary.edges_updated, summary.nodes_outside
+        );
+    }
+    let unfilled = update_elevations(&mut graph, false, |_| Some(0.0));
+    if unfilled.nodes_updated > 0 {
+        eprintln!("warning: {} node(s) without elevation were set to 0", unfilled.nodes_updated);
+    }
+    if args.check || args.repair {
+        let tolerances = CheckTolerances { climb_m: args.climb_tol, slope: args.slope_tol, length_rel: args.length_tol };
+        let report = check_consistency(&mut graph, &tolerances, args.repair);
+        print!("{}", report);
+        if !report.is_clean() && !args.repair {
+            return Err(anyhow!("{} inconsistent field(s), nothing written (pass --repair to fix them)", report.issues.len()));
+        }
+    }
+    if args.strict_segments {
+        if let Some(max) = graph.meta.max_segment_m {
+            let too_long = graph.graph.edge_weights().filter(|e| e.length > max + 1e-6).count();
+            if too_long > 0 {
+                return Err(anyhow!("{} edge(s) exceed max_segment_m, nothing written", too_long));
+            }
+        }
+    }
+    println!("Graph meta: {}", graph.meta);
+    let build = BuildInfo::new(&graph, Some(&args.input))?;
+    match args.output_format {
+        OutputFormat::Bincode => {
+            let rtree = build_spatial_index(&graph);
+            let data = AppData { graph, rtree };
+            save_data(&data, &build, &args.output)?;
+        }
+        OutputFormat::Mapped => save_mapped(&graph, &build, &args.output)?,
+    }
     println!("Preprocessed data saved to {:?}", args.output);
     Ok(())
-}
\ No newline at end of file
+}
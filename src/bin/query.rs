@@ -1,21 +1,60 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use clap::Parser;
-use project_profile_finder::application::find_route;
-use project_profile_finder::domain::{Profile, Query};
-use project_profile_finder::infrastructure::load_data;
+use project_profile_finder::application::{find_route, find_route_exact, find_routes, ExactLimits, SearchConfig};
+use project_profile_finder::domain::{Profile, Query, Route, RouteEnd};
+use project_profile_finder::infrastructure::load_network;
 use std::io;
 use std::path::PathBuf;
 
+mod common;
+use common::{EndArgs, SearchArgs};
+
 #[derive(Parser, Debug)]
 #[command(version, about = "Query preprocessed graph for routes")]
 struct Args {
     #[arg(short, long)]
     input: PathBuf,
+    #[arg(long, conflicts_with_all = ["no_edge_reuse", "simple_paths"], requires = "no_offset", help = "Exact label-setting search (best anchored area) instead of the beam search; needs --no-offset and the area matcher")]
+    exact: bool,
+    #[arg(long, value_name = "N", default_value_t = ExactLimits::default().max_labels, help = "Label limit for --exact")]
+    max_labels: usize,
+    #[arg(long, value_name = "SECONDS", default_value_t = 60.0, help = "Time limit per query for --exact")]
+    time_limit: f64,
+    #[arg(long, value_name = "K", default_value_t = 1, conflicts_with = "exact", help = "Print up to K distinct routes per query, best first")]
+    top_k: usize,
+    #[arg(long, value_name = "FRACTION", default_value_t = SearchConfig::default().min_unshared, help = "With --top-k, minimum fraction of a route's edges not in any better route")]
+    min_unshared: f64,
+    #[arg(long, value_name = "METERS", default_value_t = 0.0, help = "With --top-k, minimum distance between route start points")]
+    min_start_sep: f64,
+    #[arg(long, conflicts_with_all = ["end", "loop_radius"], help = "Each query line gives an end region after the radius: cx cy d ex ey r d1 z1 ...")]
+    query_ends: bool,
+    #[command(flatten)]
+    search: SearchArgs,
+    #[command(flatten)]
+    end: EndArgs,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let data = load_data(&args.input)?;
+    if args.exact && args.search.matcher != "area" {
+        bail!("--exact minimizes the area and needs --matcher area");
+    }
+    let (network, build) = load_network(&args.input)?;
+    let config = SearchConfig {
+        min_unshared: args.min_unshared,
+        min_start_separation: args.min_start_sep,
+        ..args.search.config()?
+    };
+    let limits = ExactLimits {
+        max_labels: args.max_labels,
+        time_limit: Some(std::time::Duration::from_secs_f64(args.time_limit)),
+        ..ExactLimits::default()
+    };
+    // stdout is reserved for route lines
+    eprintln!("Graph meta: {}", network.meta());
+    if let Some(build) = build {
+        eprintln!("Graph file: {}", build);
+    }
     let stdin = io::stdin();
     let mut lines = stdin.lines();
 
@@ -23,7 +62,8 @@ fn main() -> Result<()> {
     for _ in 0..q {
         let line = lines.next().unwrap()?;
         let parts: Vec<f64> = line.split_whitespace().map(|s| s.parse().unwrap()).collect();
-        if parts.len() < 3 || (parts.len() - 3) % 2 != 0 {
+        let head = if args.query_ends { 6 } else { 3 };
+        if parts.len() < head || !(parts.len() - head).is_multiple_of(2) {
             println!("Invalid query");
             continue;
         }
@@ -31,21 +71,49 @@ fn main() -> Result<()> {
         let cy = parts[1];
         let d = parts[2];
         let mut p_points = Vec::new();
-        for i in (3..parts.len()).step_by(2) {
+        for i in (head..parts.len()).step_by(2) {
             p_points.push((parts[i], parts[i + 1]));
         }
         let p = Profile::new(p_points);
-        let query = Query { c: (cx, cy), d, p };
-        match find_route(&data, &query)? {
-            Some(route) => {
-                print!("{:.6} {:.6}", route.si, route.ti);
-                for id in route.edge_ids {
-                    print!(" {}", id);
-                }
-                println!();
+        let end = if args.query_ends {
+            Some(RouteEnd::Region { center: (parts[3], parts[4]), radius: parts[5] })
+        } else {
+            args.end.end()
+        };
+        let query = Query { c: (cx, cy), d, p, end };
+        if args.top_k > 1 {
+            let routes = find_routes(network.as_ref(), &query, args.top_k, &config)?;
+            println!("{}", routes.len());
+            for scored in routes {
+                print!("{:.3} ", scored.score);
+                print_route(&scored.route);
+            }
+            continue;
+        }
+        let route = if args.exact {
+            let search = find_route_exact(network.as_ref(), &query, &config, &limits)?;
+            let note = if search.optimal { "optimal" } else { "search limit reached, may not be optimal" };
+            eprintln!("exact: score {:.3}, {} labels ({})", search.score, search.labels, note);
+            search.route
+        } else {
+            let found = find_route(network.as_ref(), &query, &config)?;
+            if let Some(found) = &found {
+                eprintln!("beam: score {:.3}", found.score);
             }
+            found.map(|found| found.route)
+        };
+        match route {
+            Some(route) => print_route(&route),
             None => println!("no feasible path within tolerance"),
         }
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+fn print_route(route: &Route) {
+    print!("{:.6} {:.6}", route.si, route.ti);
+    for id in &route.edge_ids {
+        print!(" {}", id);
+    }
+    println!();
+}
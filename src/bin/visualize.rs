@@ -1,11 +1,15 @@
 use anyhow::Result;
 use clap::Parser;
-use project_profile_finder::application::find_route;
-use project_profile_finder::domain::{Profile, Query};
-use project_profile_finder::infrastructure::{load_data, AppData};
+use project_profile_finder::application::{decode_route, find_route, DecodedRoute};
+use project_profile_finder::domain::{Profile, Query, RoadNetwork};
+use project_profile_finder::infrastructure::load_network;
+use petgraph::stable_graph::{EdgeIndex, NodeIndex};
 use std::path::PathBuf;
 use plotters::prelude::*;
 
+mod common;
+use common::{EndArgs, SearchArgs};
+
 #[derive(Parser, Debug)]
 #[command(version, about = "Visualize routes and elevation profiles")]
 struct Args {
@@ -23,11 +27,20 @@ struct Args {
     map_output: PathBuf,
     #[arg(short, long, default_value = "elevation_profile.png", help = "Output profile filename")]
     profile_output: PathBuf,
+    #[command(flatten)]
+    search: SearchArgs,
+    #[command(flatten)]
+    end: EndArgs,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
-    let data = load_data(&args.input)?;
+    let (network, build) = load_network(&args.input)?;
+    println!("Graph meta: {}", network.meta());
+    if let Some(build) = build {
+        println!("Graph file: {}", build);
+    }
+    let data = network.as_ref();
 
     // Parse profile points
     let profile_parts: Vec<f64> = args.profile
@@ -35,7 +48,7 @@ fn main() -> Result<()> {
         .map(|s| s.trim().parse())
         .collect::<Result<Vec<_>, _>>()?;
 
-    if profile_parts.len() % 2 != 0 {
+    if !profile_parts.len().is_multiple_of(2) {
         return Err(anyhow::anyhow!("Profile points must be in pairs (distance, elevation)"));
     }
 
@@ -49,23 +62,27 @@ fn main() -> Result<()> {
         c: (args.cx, args.cy),
         d: args.distance,
         p: target_profile.clone(),
+        end: args.end.end(),
     };
 
     println!("Searching for route near ({}, {}) within {}m radius", args.cx, args.cy, args.distance);
     println!("Target profile length: {:.1}m", target_profile.total_length());
 
-    match find_route(&data, &query)? {
-        Some(route) => {
+    match find_route(data, &query, &args.search.config()?)? {
+        Some(found) => {
+            let route = &found.route;
             println!("Found route with {} edges", route.edge_ids.len());
             println!("Route segments: si={:.3}, ti={:.3}, edges: {:?}",
                      route.si, route.ti, route.edge_ids);
+            println!("Score: {:.1} ({:.3} normalized, offset {:+.2}m), length {:.1}m ({:+.1}m vs target)",
+                     found.score, found.normalized_score, found.offset, found.length, found.length_error);
 
-            // Extract actual route profile
-            let actual_profile = extract_route_profile(&data, &route)?;
+            // Edges, polyline and actual profile of the route
+            let decoded = decode_route(data, route)?;
 
             // Create visualizations
-            create_map_visualization(&data, &query, &route, &args.map_output)?;
-            create_profile_comparison(&target_profile, &actual_profile, &args.profile_output)?;
+            create_map_visualization(data, &query, &decoded, &args.map_output)?;
+            create_profile_comparison(&target_profile, &decoded.profile, &args.profile_output)?;
 
             println!("Map saved to: {:?}", args.map_output);
             println!("Profile comparison saved to: {:?}", args.profile_output);
@@ -74,7 +91,7 @@ fn main() -> Result<()> {
             println!("No feasible route found within tolerance");
 
             // Still create map showing search area
-            create_search_area_visualization(&data, &query, &args.map_output)?;
+            create_search_area_visualization(data, &query, &args.map_output)?;
             println!("Search area map saved to: {:?}", args.map_output);
         }
     }
@@ -82,51 +99,28 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn extract_route_profile(data: &AppData, route: &project_profile_finder::domain::Route) -> Result<Profile> {
-    let mut points = vec![(0.0, 0.0)];
-    let mut cumulative_distance = 0.0;
-    let mut cumulative_elevation = 0.0;
-
-    for (i, &edge_id) in route.edge_ids.iter().enumerate() {
-        // Find the edge in the graph
-        let mut edge_data = None;
-        for e_idx in data.graph.graph.edge_indices() {
-            if data.graph.graph[e_idx].id == edge_id {
-                edge_data = Some(&data.graph.graph[e_idx]);
-                break;
-            }
-        }
-
-        let edge = edge_data.ok_or_else(|| anyhow::anyhow!("Edge {} not found", edge_id))?;
-
-        let (length, climb) = if i == 0 && i == route.edge_ids.len() - 1 {
-            // Single edge, use both si and ti
-            let total_fraction = route.ti - route.si;
-            (edge.length * total_fraction, edge.climb * total_fraction)
-        } else if i == 0 {
-            // First edge, use from si to end
-            let fraction = 1.0 - route.si;
-            (edge.length * fraction, edge.climb * fraction)
-        } else if i == route.edge_ids.len() - 1 {
-            // Last edge, use from start to ti
-            (edge.length * route.ti, edge.climb * route.ti)
-        } else {
-            // Middle edge, use entirely
-            (edge.length, edge.climb)
-        };
-
-        cumulative_distance += length;
-        cumulative_elevation += climb;
-        points.push((cumulative_distance, cumulative_elevation));
+// Polyline of an edge in map coordinates
+fn edge_line(data: &dyn RoadNetwork, e_idx: EdgeIndex) -> Vec<(f64, f64)> {
+    if let Some(geometry) = &data.edge(e_idx).geometry {
+        return geometry.points.iter().map(|p| (p[0], p[1])).collect();
     }
+    let (u, v) = data.edge_endpoints(e_idx);
+    let (node_u, node_v) = (data.node(u), data.node(v));
+    vec![(node_u.x, node_u.y), (node_v.x, node_v.y)]
+}
 
-    Ok(Profile { points })
+// Every edge, reached through its source node; node indices are contiguous in both stores
+fn all_edges(data: &dyn RoadNetwork) -> Vec<EdgeIndex> {
+    (0..data.node_count())
+        .flat_map(|n| data.out_edges(NodeIndex::new(n)))
+        .map(|(e, _)| e)
+        .collect()
 }
 
 fn create_map_visualization(
-    data: &AppData,
+    data: &dyn RoadNetwork,
     query: &Query,
-    route: &project_profile_finder::domain::Route,
+    route: &DecodedRoute,
     output_path: &PathBuf,
 ) -> Result<()> {
     let root = BitMapBackend::new(output_path, (800, 600)).into_drawing_area();
@@ -138,7 +132,7 @@ fn create_map_visualization(
     let mut min_y = f64::INFINITY;
     let mut max_y = f64::NEG_INFINITY;
 
-    for node in data.graph.graph.node_weights() {
+    for node in (0..data.node_count()).map(|n| data.node(NodeIndex::new(n))) {
         min_x = min_x.min(node.x);
         max_x = max_x.max(node.x);
         min_y = min_y.min(node.y);
@@ -153,7 +147,7 @@ fn create_map_visualization(
     max_y += padding;
 
     let mut chart = ChartBuilder::on(&root)
-        .caption("Route Map", ("sans-serif", 30))
+        .caption(format!("Route Map ({})", data.meta().crs.as_deref().unwrap_or("unknown CRS")), ("sans-serif", 30))
         .margin(5)
         .x_label_area_size(40)
         .y_label_area_size(40)
@@ -162,13 +156,9 @@ fn create_map_visualization(
     chart.configure_mesh().draw()?;
 
     // Draw all edges in light gray
-    for e_idx in data.graph.graph.edge_indices() {
-        let (u, v) = data.graph.graph.edge_endpoints(e_idx).unwrap();
-        let node_u = &data.graph.graph[u];
-        let node_v = &data.graph.graph[v];
-
+    for e_idx in all_edges(data) {
         chart.draw_series(LineSeries::new(
-            vec![(node_u.x, node_u.y), (node_v.x, node_v.y)],
+            edge_line(data, e_idx),
             &RGBColor(128, 128, 128).mix(0.3),
         ))?;
     }
@@ -189,21 +179,10 @@ fn create_map_visualization(
         .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], BLUE));
 
     // Draw the found route in red
-    for &edge_id in &route.edge_ids {
-        for e_idx in data.graph.graph.edge_indices() {
-            if data.graph.graph[e_idx].id == edge_id {
-                let (u, v) = data.graph.graph.edge_endpoints(e_idx).unwrap();
-                let node_u = &data.graph.graph[u];
-                let node_v = &data.graph.graph[v];
-
-                chart.draw_series(LineSeries::new(
-                    vec![(node_u.x, node_u.y), (node_v.x, node_v.y)],
-                    RED.stroke_width(3),
-                ))?;
-                break;
-            }
-        }
-    }
+    chart.draw_series(LineSeries::new(
+        route.geometry.iter().map(|p| (p[0], p[1])),
+        RED.stroke_width(3),
+    ))?;
 
     // Mark center point
     chart.draw_series(PointSeries::of_element(
@@ -224,7 +203,7 @@ fn create_map_visualization(
 }
 
 fn create_search_area_visualization(
-    data: &AppData,
+    data: &dyn RoadNetwork,
     query: &Query,
     output_path: &PathBuf,
 ) -> Result<()> {
@@ -237,7 +216,7 @@ fn create_search_area_visualization(
     let mut min_y = f64::INFINITY;
     let mut max_y = f64::NEG_INFINITY;
 
-    for node in data.graph.graph.node_weights() {
+    for node in (0..data.node_count()).map(|n| data.node(NodeIndex::new(n))) {
         min_x = min_x.min(node.x);
         max_x = max_x.max(node.x);
         min_y = min_y.min(node.y);
@@ -260,13 +239,9 @@ fn create_search_area_visualization(
     chart.configure_mesh().draw()?;
 
     // Draw all edges
-    for e_idx in data.graph.graph.edge_indices() {
-        let (u, v) = data.graph.graph.edge_endpoints(e_idx).unwrap();
-        let node_u = &data.graph.graph[u];
-        let node_v = &data.graph.graph[v];
-
+    for e_idx in all_edges(data) {
         chart.draw_series(LineSeries::new(
-            vec![(node_u.x, node_u.y), (node_v.x, node_v.y)],
+            edge_line(data, e_idx),
             RGBColor(128, 128, 128).mix(0.5),
         ))?;
     }
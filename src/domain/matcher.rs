@@ -2,6 +2,70 @@ use super::models::Profile;
 
 pub trait ProfileMatcher {
     fn score(&self, actual: &Profile, target: &Profile) -> f64;
+    // Vertical shift applied to `actual` before scoring
+    fn offset(&self, _actual: &Profile, _target: &Profile) -> f64 {
+        0.0
+    }
+    // `score` made comparable across targets of different lengths
+    fn normalized_score(&self, score: f64, target_length: f64) -> f64 {
+        score / target_length
+    }
+    // Cost one stretch of route adds to a partial route, used to rank partial
+    // routes during the search. `shape` holds (distance, elevation) points
+    // relative to the stretch's start, which lies at distance `s0` and elevation
+    // `rel0` along the route. Defaults to the area against the target with no offset.
+    fn piece_cost(&self, s0: f64, rel0: f64, shape: &[(f64, f64)], target: &Profile) -> f64 {
+        stretch_pieces(s0, rel0, shape, target).into_iter().map(|(len, a, b)| integral_abs_diff(len, a, b)).sum()
+    }
+}
+
+// Settings a matcher from the registry is built with
+#[derive(Clone, Copy, Debug)]
+pub struct MatcherOptions {
+    pub use_offset: bool,
+    // How far, in meters, a warping matcher may shift the route against the target
+    pub warp_window: f64,
+    // Weight of gradient differences where the gradient matchers find a climb
+    pub climb_weight: f64,
+}
+
+pub struct MatcherEntry {
+    pub name: &'static str,
+    pub about: &'static str,
+    pub build: fn(&MatcherOptions) -> Box<dyn ProfileMatcher>,
+}
+
+// Matchers selectable by name, e.g. with `query --matcher`
+pub const MATCHERS: &[MatcherEntry] = &[
+    MatcherEntry {
+        name: "area",
+        about: "area between the profiles (L1)",
+        build: |options| Box::new(AreaMatcher { use_offset: options.use_offset }),
+    },
+    MatcherEntry {
+        name: "rmse",
+        about: "root mean square difference in meters (L2)",
+        build: |options| Box::new(RmseMatcher { use_offset: options.use_offset }),
+    },
+    MatcherEntry {
+        name: "dtw",
+        about: "area after aligning the profiles by dynamic time warping",
+        build: |options| Box::new(DtwMatcher { use_offset: options.use_offset, window: options.warp_window }),
+    },
+    MatcherEntry {
+        name: "gradient",
+        about: "absolute gradient difference integrated over distance",
+        build: |options| Box::new(GradientMatcher { squared: false, climb_weight: options.climb_weight }),
+    },
+    MatcherEntry {
+        name: "gradient-sq",
+        about: "squared gradient difference integrated over distance",
+        build: |options| Box::new(GradientMatcher { squared: true, climb_weight: options.climb_weight }),
+    },
+];
+
+pub fn matcher_by_name(name: &str, options: &MatcherOptions) -> Option<Box<dyn ProfileMatcher>> {
+    MATCHERS.iter().find(|m| m.name == name).map(|m| (m.build)(options))
 }
 
 pub struct AreaMatcher {
@@ -14,70 +78,285 @@ impl ProfileMatcher for AreaMatcher {
         if l == 0.0 {
             return 0.0;
         }
-        // To compute integral |actual(s) - target(s) - z0| ds
-        // First, compute without offset
-        let mut area = 0.0;
-        let mut i_a = 0;
-        let mut i_t = 0;
-        let mut s = 0.0;
-        while i_a < actual.points.len() - 1 || i_t < target.points.len() - 1 {
-            let next_s_a = if i_a < actual.points.len() - 1 { actual.points[i_a + 1].0 } else { f64::MAX };
-            let next_s_t = if i_t < target.points.len() - 1 { target.points[i_t + 1].0 } else { f64::MAX };
-            let next_s = next_s_a.min(next_s_t);
-            let len = next_s - s;
-            if len > 0.0 {
-                let a_start = actual.interpolate(s);
-                let a_end = actual.interpolate(next_s);
-                let t_start = target.interpolate(s);
-                let t_end = target.interpolate(next_s);
-                area += integral_abs_diff(len, a_start - t_start, a_end - t_end);
-            }
-            s = next_s;
-            if next_s == next_s_a {
-                i_a += 1;
-            }
-            if next_s == next_s_t {
-                i_t += 1;
-            }
+        // Integral of |actual(s) + z0 - target(s)| ds
+        let pieces = diff_pieces(actual, target);
+        let z0 = if self.use_offset { l1_offset(&pieces) } else { 0.0 };
+        pieces.iter().map(|&(len, a, b)| integral_abs_diff(len, a + z0, b + z0)).sum()
+    }
+
+    fn offset(&self, actual: &Profile, target: &Profile) -> f64 {
+        if !self.use_offset || target.total_length() == 0.0 {
+            return 0.0;
         }
-        if !self.use_offset {
-            return area;
+        l1_offset(&diff_pieces(actual, target))
+    }
+}
+
+// Root mean square difference between the profiles, in meters. Squaring
+// weighs a single large deviation more than the area does.
+pub struct RmseMatcher {
+    pub use_offset: bool,
+}
+
+impl ProfileMatcher for RmseMatcher {
+    fn score(&self, actual: &Profile, target: &Profile) -> f64 {
+        if target.total_length() == 0.0 {
+            return 0.0;
         }
-        // Simple offset using average at sample points
-        let samples = &target.points;
-        let mut sum_diff = 0.0;
-        for p in samples {
-            sum_diff += actual.interpolate(p.0) - p.1;
+        let pieces = diff_pieces(actual, target);
+        let l: f64 = pieces.iter().map(|p| p.0).sum();
+        if l <= 0.0 {
+            return 0.0;
         }
-        let z0 = -sum_diff / samples.len() as f64;
-        // Recompute area with z0
-        let mut area_offset = 0.0;
-        // Similar loop, but add z0 to actual (or subtract from diff)
-        let mut i_a = 0;
-        let mut i_t = 0;
-        let mut s = 0.0;
-        while i_a < actual.points.len() - 1 || i_t < target.points.len() - 1 {
-            let next_s_a = if i_a < actual.points.len() - 1 { actual.points[i_a + 1].0 } else { f64::MAX };
-            let next_s_t = if i_t < target.points.len() - 1 { target.points[i_t + 1].0 } else { f64::MAX };
-            let next_s = next_s_a.min(next_s_t);
-            let len = next_s - s;
-            if len > 0.0 {
-                let a_start = actual.interpolate(s) + z0;
-                let a_end = actual.interpolate(next_s) + z0;
-                let t_start = target.interpolate(s);
-                let t_end = target.interpolate(next_s);
-                area_offset += integral_abs_diff(len, a_start - t_start, a_end - t_end);
-            }
-            s = next_s;
-            if next_s == next_s_a {
-                i_a += 1;
-            }
-            if next_s == next_s_t {
-                i_t += 1;
+        let z0 = if self.use_offset { l2_offset(&pieces) } else { 0.0 };
+        let squares: f64 = pieces.iter().map(|&(len, a, b)| integral_square(len, a + z0, b + z0)).sum();
+        (squares / l).sqrt()
+    }
+
+    fn offset(&self, actual: &Profile, target: &Profile) -> f64 {
+        if !self.use_offset || target.total_length() == 0.0 {
+            return 0.0;
+        }
+        l2_offset(&diff_pieces(actual, target))
+    }
+
+    // Already per meter
+    fn normalized_score(&self, score: f64, _target_length: f64) -> f64 {
+        score
+    }
+
+    // Integral of the squared difference; its square root over the length is
+    // the score, so partial routes rank the same way
+    fn piece_cost(&self, s0: f64, rel0: f64, shape: &[(f64, f64)], target: &Profile) -> f64 {
+        stretch_pieces(s0, rel0, shape, target).into_iter().map(|(len, a, b)| integral_square(len, a, b)).sum()
+    }
+}
+
+// Profiles are resampled this finely for warping, unless that takes more samples
+const DTW_STEP_M: f64 = 5.0;
+const DTW_MAX_SAMPLES: usize = 2000;
+
+// Area between the profiles after dynamic time warping: a climb that comes a
+// little later or is a little longer than in the target still lines up with
+// it. Both profiles are resampled to the same number of points, which may pair
+// up at most `window` meters from where their share of the length puts them;
+// a move to the next pair costs the difference there times half the distance
+// it advances along each profile, so with no warping the score approximates
+// the area. The offset is the area matcher's, taken before warping. Partial
+// routes are ranked by area.
+pub struct DtwMatcher {
+    pub use_offset: bool,
+    pub window: f64,
+}
+
+impl ProfileMatcher for DtwMatcher {
+    fn score(&self, actual: &Profile, target: &Profile) -> f64 {
+        let (la, lt) = (actual.total_length(), target.total_length());
+        if lt == 0.0 {
+            return 0.0;
+        }
+        let z0 = self.offset(actual, target);
+        let n = ((la.max(lt) / DTW_STEP_M).ceil() as usize).clamp(1, DTW_MAX_SAMPLES);
+        let (ha, ht) = (la / n as f64, lt / n as f64);
+        let a: Vec<f64> = (0..=n).map(|i| actual.interpolate(i as f64 * ha) + z0).collect();
+        let t: Vec<f64> = (0..=n).map(|j| target.interpolate(j as f64 * ht)).collect();
+        // A window past the whole profile allows no more than one covering it
+        let r = ((self.window.max(0.0) / ha.max(ht)).round() as usize).min(n);
+
+        // Cheapest cost to reach (i, j) for |i - j| <= r, stored at j + r - i
+        let width = 2 * r + 1;
+        let mut prev = vec![f64::INFINITY; width];
+        let mut cur = vec![f64::INFINITY; width];
+        for (i, &a_i) in a.iter().enumerate() {
+            cur.fill(f64::INFINITY);
+            for (j, &t_j) in t.iter().enumerate().take(i + r + 1).skip(i.saturating_sub(r)) {
+                let k = j + r - i;
+                if i == 0 && j == 0 {
+                    cur[k] = 0.0;
+                    continue;
+                }
+                let diff = (a_i - t_j).abs();
+                let mut best = f64::INFINITY;
+                if i > 0 && j > 0 {
+                    best = best.min(prev[k] + diff * (ha + ht) / 2.0);
+                }
+                if i > 0 && k + 1 < width {
+                    best = best.min(prev[k + 1] + diff * ha / 2.0);
+                }
+                if j > 0 && k > 0 {
+                    best = best.min(cur[k - 1] + diff * ht / 2.0);
+                }
+                cur[k] = best;
             }
+            std::mem::swap(&mut prev, &mut cur);
+        }
+        prev[r]
+    }
+
+    fn offset(&self, actual: &Profile, target: &Profile) -> f64 {
+        if !self.use_offset || target.total_length() == 0.0 {
+            return 0.0;
+        }
+        l1_offset(&diff_pieces(actual, target))
+    }
+}
+
+// Compares slopes instead of elevations, so a mismatch early on does not
+// shift the rest of the route against the target. Both profiles are constant
+// in gradient between their merged breakpoints; the score integrates the
+// absolute or squared gradient difference over distance, weighted by
+// `climb_weight` where either profile climbs. Gradients ignore any vertical
+// offset.
+pub struct GradientMatcher {
+    pub squared: bool,
+    pub climb_weight: f64,
+}
+
+impl GradientMatcher {
+    fn cost(&self, len: f64, actual: f64, target: f64) -> f64 {
+        let diff = (actual - target).abs();
+        let weight = if actual > 0.0 || target > 0.0 { self.climb_weight } else { 1.0 };
+        weight * len * if self.squared { diff * diff } else { diff }
+    }
+}
+
+impl ProfileMatcher for GradientMatcher {
+    fn score(&self, actual: &Profile, target: &Profile) -> f64 {
+        if target.total_length() == 0.0 {
+            return 0.0;
         }
-        area_offset
+        merged_breaks(actual, target)
+            .windows(2)
+            .filter(|w| w[1] > w[0])
+            .map(|w| {
+                let len = w[1] - w[0];
+                let grade_a = (actual.interpolate(w[1]) - actual.interpolate(w[0])) / len;
+                let grade_t = (target.interpolate(w[1]) - target.interpolate(w[0])) / len;
+                self.cost(len, grade_a, grade_t)
+            })
+            .sum()
+    }
+
+    // Each segment of the shape has one gradient, the edge slope for an edge
+    // without interior elevations; it is split where the target's changes
+    fn piece_cost(&self, s0: f64, _rel0: f64, shape: &[(f64, f64)], target: &Profile) -> f64 {
+        shape
+            .windows(2)
+            .filter(|w| w[1].0 > w[0].0)
+            .map(|w| {
+                let (a, b) = (w[0], w[1]);
+                let grade_a = (b.1 - a.1) / (b.0 - a.0);
+                let (from, to) = (s0 + a.0, s0 + b.0);
+                let mut knots = vec![from];
+                knots.extend(target.points.iter().map(|p| p.0).filter(|&k| k > from && k < to));
+                knots.push(to);
+                knots
+                    .windows(2)
+                    .filter(|k| k[1] > k[0])
+                    .map(|k| {
+                        let len = k[1] - k[0];
+                        self.cost(len, grade_a, (target.interpolate(k[1]) - target.interpolate(k[0])) / len)
+                    })
+                    .sum::<f64>()
+            })
+            .sum()
+    }
+}
+
+// (length, diff at start, diff at end) of a stretch of route against the
+// target, for `shape` points relative to the stretch's start at distance `s0`
+// and elevation `rel0`. Split where either has a point, so the difference is
+// linear on each piece.
+pub fn stretch_pieces(s0: f64, rel0: f64, shape: &[(f64, f64)], target: &Profile) -> Vec<(f64, f64, f64)> {
+    let mut pieces = Vec::new();
+    for w in shape.windows(2).filter(|w| w[1].0 > w[0].0) {
+        let ((d0, z0), (d1, z1)) = (w[0], w[1]);
+        let at = |s: f64| rel0 + z0 + (z1 - z0) * (s - s0 - d0) / (d1 - d0) - target.interpolate(s);
+        let mut knots = vec![s0 + d0];
+        knots.extend(target.points.iter().map(|p| p.0).filter(|&k| k > s0 + d0 && k < s0 + d1));
+        knots.push(s0 + d1);
+        pieces.extend(knots.windows(2).filter(|k| k[1] > k[0]).map(|k| (k[1] - k[0], at(k[0]), at(k[1]))));
     }
+    pieces
+}
+
+// Sorted distances where either profile has a point, up to the longer one
+fn merged_breaks(actual: &Profile, target: &Profile) -> Vec<f64> {
+    let mut breaks: Vec<f64> = actual.points.iter().chain(&target.points).map(|p| p.0).collect();
+    breaks.sort_by(f64::total_cmp);
+    breaks.dedup();
+    breaks
+}
+
+// (length, diff at start, diff at end) of actual - target between the merged
+// breakpoints of both profiles; the difference is linear on each piece
+fn diff_pieces(actual: &Profile, target: &Profile) -> Vec<(f64, f64, f64)> {
+    merged_breaks(actual, target)
+        .windows(2)
+        .filter(|w| w[1] > w[0])
+        .map(|w| {
+            let (s0, s1) = (w[0], w[1]);
+            (s1 - s0, actual.interpolate(s0) - target.interpolate(s0), actual.interpolate(s1) - target.interpolate(s1))
+        })
+        .collect()
+}
+
+// The offset z minimizing the sum of integrals of |diff + z|: minus the
+// length-weighted median of the diff values. A sloped piece spreads its length
+// evenly over the values between its end diffs, a flat one puts it all on one
+// value, so the length below a value is piecewise linear between piece ends;
+// find the bracket where it passes half the total and solve within it.
+fn l1_offset(pieces: &[(f64, f64, f64)]) -> f64 {
+    let total: f64 = pieces.iter().map(|p| p.0).sum();
+    if total <= 0.0 {
+        return 0.0;
+    }
+    let half = total / 2.0;
+    let mut values: Vec<f64> = pieces.iter().flat_map(|&(_, a, b)| [a, b]).collect();
+    values.sort_by(f64::total_cmp);
+    let k = values.partition_point(|&v| length_below(pieces, v, true) < half).min(values.len() - 1);
+    let v = values[k];
+    if k == 0 {
+        return -v;
+    }
+    let p = values[k - 1];
+    let (below_p, below_v) = (length_below(pieces, p, true), length_below(pieces, v, false));
+    if below_v > below_p && below_v >= half {
+        return -(p + (half - below_p) / (below_v - below_p) * (v - p));
+    }
+    -v
+}
+
+// Length of the pieces where diff is below `v`, or at most `v` when `inclusive`
+fn length_below(pieces: &[(f64, f64, f64)], v: f64, inclusive: bool) -> f64 {
+    pieces
+        .iter()
+        .map(|&(len, a, b)| {
+            let (lo, hi) = (a.min(b), a.max(b));
+            if hi > lo {
+                len * ((v - lo) / (hi - lo)).clamp(0.0, 1.0)
+            } else if v > lo || (inclusive && v == lo) {
+                len
+            } else {
+                0.0
+            }
+        })
+        .sum()
+}
+
+// The offset z minimizing the sum of integrals of (diff + z)^2: minus the
+// length-weighted mean of diff, each piece contributing its midpoint value
+fn l2_offset(pieces: &[(f64, f64, f64)]) -> f64 {
+    let total: f64 = pieces.iter().map(|p| p.0).sum();
+    if total <= 0.0 {
+        return 0.0;
+    }
+    -pieces.iter().map(|&(len, a, b)| len * (a + b) / 2.0).sum::<f64>() / total
+}
+
+// Integral of (diff_start + t/len * (diff_end - diff_start))^2 dt over [0, len]
+pub fn integral_square(len: f64, diff_start: f64, diff_end: f64) -> f64 {
+    len * (diff_start * diff_start + diff_start * diff_end + diff_end * diff_end) / 3.0
 }
 
 // Integral of |diff_start + t/len * (diff_end - diff_start)| dt over [0, len]
@@ -90,4 +369,101 @@ pub fn integral_abs_diff(len: f64, diff_start: f64, diff_end: f64) -> f64 {
     let area1 = diff_start.abs() * t0 / 2.0;  // Triangle
     let area2 = diff_end.abs() * (len - t0) / 2.0;
     area1 + area2
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Area with `actual` shifted up by `z`, integrated independently of `l1_offset`
+    fn area_at(actual: &Profile, target: &Profile, z: f64) -> f64 {
+        diff_pieces(actual, target).iter().map(|&(len, a, b)| integral_abs_diff(len, a + z, b + z)).sum()
+    }
+
+    fn assert_optimal(actual: &Profile, target: &Profile) {
+        let matcher = AreaMatcher { use_offset: true };
+        let (z, score) = (matcher.offset(actual, target), matcher.score(actual, target));
+        let best = area_at(actual, target, z);
+        let slack = 1e-9 * (1.0 + best);
+        assert!((score - best).abs() <= slack, "score {score} vs area at offset {best}");
+        for k in -80..=80 {
+            for dz in [k as f64 * 0.25, k as f64 * 1e-4] {
+                let other = area_at(actual, target, z + dz);
+                assert!(other >= best - slack, "offset {} scores {other} < {best} at {z}", z + dz);
+            }
+        }
+    }
+
+    // Deterministic random profile of `n` segments and about `length` meters,
+    // with some flat stretches
+    fn random_profile(next: &mut impl FnMut() -> f64, n: usize, length: f64) -> Profile {
+        let mut points = vec![(0.0, 0.0)];
+        for _ in 0..n {
+            let (s, z) = points[points.len() - 1];
+            let z = if next() < 0.3 { z } else { (next() - 0.5) * 40.0 };
+            points.push((s + 1.0 + next() * 50.0, z));
+        }
+        let scale = length / points[points.len() - 1].0;
+        Profile::new(points.into_iter().map(|(s, z)| (s * scale, z)).collect())
+    }
+
+    #[test]
+    fn l1_offset_is_optimal_for_random_profiles() {
+        let mut seed: u64 = 12345;
+        let mut next = move || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 11) as f64 / (1u64 << 53) as f64
+        };
+        for case in 0..2000 {
+            let target_len = 50.0 + next() * 400.0;
+            // Every fourth pair differs in length
+            let actual_len = if case % 4 == 0 { target_len * (0.8 + next() * 0.4) } else { target_len };
+            let (na, nt) = (1 + (next() * 8.0) as usize, 1 + (next() * 8.0) as usize);
+            let actual = random_profile(&mut next, na, actual_len);
+            let target = random_profile(&mut next, nt, target_len);
+            assert_optimal(&actual, &target);
+        }
+    }
+
+    #[test]
+    fn l1_offset_with_flat_pieces() {
+        // Diff is -10 on 99 m of flat and rises from 0 over the first meter
+        let actual = Profile::new(vec![(0.0, 0.0), (100.0, 0.0)]);
+        let target = Profile::new(vec![(0.0, 0.0), (1.0, 10.0), (100.0, 10.0)]);
+        let matcher = AreaMatcher { use_offset: true };
+        assert!((matcher.offset(&actual, &target) - 10.0).abs() < 1e-9);
+        assert!((matcher.score(&actual, &target) - 5.0).abs() < 1e-9);
+        assert_optimal(&actual, &target);
+    }
+
+    #[test]
+    fn l1_offset_with_unequal_lengths() {
+        // The route runs 20 m past the target, which holds its last elevation;
+        // diff spreads evenly over 0..12 across all 120 m
+        let actual = Profile::new(vec![(0.0, 0.0), (120.0, 12.0)]);
+        let target = Profile::new(vec![(0.0, 0.0), (100.0, 0.0)]);
+        let matcher = AreaMatcher { use_offset: true };
+        assert!((matcher.offset(&actual, &target) + 6.0).abs() < 1e-9);
+        assert!((matcher.score(&actual, &target) - 360.0).abs() < 1e-9);
+        assert_optimal(&actual, &target);
+        assert_optimal(&target, &actual);
+    }
+
+    #[test]
+    fn l1_offset_on_a_jump_at_the_median() {
+        // Exactly half the length lies below 10, and the other half sits at 10
+        let actual = Profile::new(vec![(0.0, 0.0), (50.0, 10.0), (100.0, 10.0)]);
+        let target = Profile::new(vec![(0.0, 0.0), (100.0, 0.0)]);
+        let matcher = AreaMatcher { use_offset: true };
+        assert!((matcher.offset(&actual, &target) + 10.0).abs() < 1e-9);
+        assert!((matcher.score(&actual, &target) - 250.0).abs() < 1e-9);
+        assert_optimal(&actual, &target);
+
+        // Two flat halves 10 m apart: every offset between them is optimal
+        let actual = Profile::new(vec![(0.0, 0.0), (50.0, 0.0), (50.000001, 10.0), (100.0, 10.0)]);
+        let z = matcher.offset(&actual, &target);
+        assert!((-10.0..=0.0).contains(&z), "offset {z}");
+        assert!((matcher.score(&actual, &target) - 500.0).abs() < 1e-3);
+        assert_optimal(&actual, &target);
+    }
+}
@@ -1,6 +1,8 @@
 mod models;
 mod matcher;
+mod network;
 
 
 pub use models::*;
-pub use matcher::*;
\ No newline at end of file
+pub use matcher::*;
+pub use network::*;
\ No newline at end of file
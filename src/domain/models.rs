@@ -1,6 +1,8 @@
-use petgraph::stable_graph::{NodeIndex, StableGraph};
+use petgraph::stable_graph::{EdgeIndex, NodeIndex, StableGraph};
 use hashbrown::HashMap;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct NodeData {
@@ -15,12 +17,238 @@ pub struct EdgeData {
     pub length: f64,
     pub climb: f64,
     pub slope: f64,
+    pub origin: Option<EdgeOrigin>,  // Set on sub-edges created by splitting a long edge
+    pub geometry: Option<EdgeGeometry>,  // None means a straight line from u to v
+}
+
+// Polyline of an edge from u to v (endpoints included), optionally with an
+// elevation per vertex. Fractions along it are by planar arc length.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EdgeGeometry {
+    pub points: Vec<[f64; 2]>,
+    pub elevs: Option<Vec<f64>>,
+}
+
+impl EdgeGeometry {
+    // Cumulative planar distance at each vertex
+    pub fn cumulative(&self) -> Vec<f64> {
+        let mut cum = Vec::with_capacity(self.points.len());
+        let mut s = 0.0;
+        cum.push(0.0);
+        for w in self.points.windows(2) {
+            s += ((w[1][0] - w[0][0]).powi(2) + (w[1][1] - w[0][1]).powi(2)).sqrt();
+            cum.push(s);
+        }
+        cum
+    }
+
+    pub fn length_2d(&self) -> f64 {
+        self.cumulative().last().copied().unwrap_or(0.0)
+    }
+
+    // Vertex fractions (0.0 ..= 1.0) along the polyline
+    pub fn fractions(&self) -> Vec<f64> {
+        let cum = self.cumulative();
+        let total = cum.last().copied().unwrap_or(0.0);
+        if total > 0.0 {
+            cum.iter().map(|s| s / total).collect()
+        } else {
+            // Degenerate polyline, spread vertices evenly
+            let n = (self.points.len() - 1).max(1) as f64;
+            (0..self.points.len()).map(|i| i as f64 / n).collect()
+        }
+    }
+
+    // Segment index and position within it for a fraction along the polyline
+    fn locate(&self, fractions: &[f64], fraction: f64) -> (usize, f64) {
+        let fraction = fraction.clamp(0.0, 1.0);
+        let i = fractions.partition_point(|&f| f <= fraction).clamp(1, fractions.len() - 1) - 1;
+        let span = fractions[i + 1] - fractions[i];
+        let t = if span > 0.0 { (fraction - fractions[i]) / span } else { 0.0 };
+        (i, t)
+    }
+
+    pub fn point_at(&self, fraction: f64) -> [f64; 2] {
+        let (i, t) = self.locate(&self.fractions(), fraction);
+        let (a, b) = (self.points[i], self.points[i + 1]);
+        [a[0] + t * (b[0] - a[0]), a[1] + t * (b[1] - a[1])]
+    }
+
+    pub fn elev_at(&self, fraction: f64) -> Option<f64> {
+        let elevs = self.elevs.as_ref()?;
+        let (i, t) = self.locate(&self.fractions(), fraction);
+        Some(elevs[i] + t * (elevs[i + 1] - elevs[i]))
+    }
+
+    // The part of the polyline between two fractions, with interpolated end vertices
+    pub fn slice(&self, from: f64, to: f64) -> EdgeGeometry {
+        let fractions = self.fractions();
+        let mut points = vec![self.point_at(from)];
+        let mut elevs = self.elevs.as_ref().map(|_| vec![self.elev_at(from).unwrap()]);
+        for (i, &f) in fractions.iter().enumerate() {
+            if f > from && f < to {
+                points.push(self.points[i]);
+                if let (Some(out), Some(src)) = (elevs.as_mut(), self.elevs.as_ref()) {
+                    out.push(src[i]);
+                }
+            }
+        }
+        points.push(self.point_at(to));
+        if let Some(out) = elevs.as_mut() {
+            out.push(self.elev_at(to).unwrap());
+        }
+        EdgeGeometry { points, elevs }
+    }
+}
+
+// Which part of a source edge a sub-edge covers, as fractions along it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct EdgeOrigin {
+    pub id: u64,
+    pub from: f64,
+    pub to: f64,
+}
+
+impl EdgeData {
+    pub fn source_id(&self) -> u64 {
+        self.origin.map(|o| o.id).unwrap_or(self.id)
+    }
+
+    // Elevation relative to the start of the edge at a fraction along it. Follows the
+    // per-vertex elevations when the geometry has them (pinned so the end matches
+    // `climb`), otherwise a linear ramp.
+    pub fn rel_elev_at(&self, fraction: f64) -> f64 {
+        if let Some(geometry) = &self.geometry {
+            if let (Some(start), Some(end), Some(at)) = (geometry.elev_at(0.0), geometry.elev_at(1.0), geometry.elev_at(fraction)) {
+                return at - start + (self.climb - (end - start)) * fraction;
+            }
+        }
+        fraction * self.climb
+    }
+
+    // Profile points (distance, elevation) of the part of the edge between two
+    // fractions, relative to the point at `from`. Includes interior vertices.
+    pub fn profile_between(&self, from: f64, to: f64) -> Vec<(f64, f64)> {
+        let base = self.rel_elev_at(from);
+        let mut points = vec![(0.0, 0.0)];
+        if let Some(geometry) = self.geometry.as_ref().filter(|g| g.elevs.is_some()) {
+            for f in geometry.fractions() {
+                if f > from && f < to {
+                    points.push(((f - from) * self.length, self.rel_elev_at(f) - base));
+                }
+            }
+        }
+        points.push(((to - from) * self.length, self.rel_elev_at(to) - base));
+        points
+    }
+
+    // Map a fraction along this edge to a fraction along its source edge
+    pub fn source_fraction(&self, fraction: f64) -> f64 {
+        match self.origin {
+            Some(o) => o.from + fraction * (o.to - o.from),
+            None => fraction,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum LengthUnit {
+    #[default]
+    Meters,
+    Kilometers,
+    Feet,
+    UsSurveyFeet,
+}
+
+impl LengthUnit {
+    pub fn to_meters(self) -> f64 {
+        match self {
+            LengthUnit::Meters => 1.0,
+            LengthUnit::Kilometers => 1000.0,
+            LengthUnit::Feet => 0.3048,
+            LengthUnit::UsSurveyFeet => 1200.0 / 3937.0,
+        }
+    }
+}
+
+impl FromStr for LengthUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "m" | "meter" | "meters" | "metre" | "metres" => Ok(LengthUnit::Meters),
+            "km" | "kilometer" | "kilometers" | "kilometre" | "kilometres" => Ok(LengthUnit::Kilometers),
+            "ft" | "foot" | "feet" => Ok(LengthUnit::Feet),
+            "us-ft" | "us_survey_feet" | "us survey feet" | "us-feet" => Ok(LengthUnit::UsSurveyFeet),
+            other => Err(format!("unsupported units {:?}", other)),
+        }
+    }
+}
+
+impl fmt::Display for LengthUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LengthUnit::Meters => "meters",
+            LengthUnit::Kilometers => "kilometers",
+            LengthUnit::Feet => "feet",
+            LengthUnit::UsSurveyFeet => "us-ft",
+        };
+        f.write_str(name)
+    }
+}
+
+// Contents of the JSONL meta record. `units` is what the source file used;
+// the graph itself is always stored in meters after ingest.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GraphMeta {
+    pub crs: Option<String>,
+    pub units: LengthUnit,
+    pub max_segment_m: Option<f64>,
+}
+
+impl fmt::Display for GraphMeta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "CRS {}, source units {}", self.crs.as_deref().unwrap_or("unknown"), self.units)?;
+        if let Some(max) = self.max_segment_m {
+            write!(f, ", max segment {}m", max)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct RoadGraph {
     pub graph: StableGraph<NodeData, EdgeData>,
     pub node_map: HashMap<u64, NodeIndex>,
+    // Source edge id -> the edges that carry it, in order along it (one unless it was split)
+    pub edge_map: HashMap<u64, Vec<EdgeIndex>>,
+    pub meta: GraphMeta,
+}
+
+impl RoadGraph {
+    pub fn new(graph: StableGraph<NodeData, EdgeData>, node_map: HashMap<u64, NodeIndex>, meta: GraphMeta) -> Self {
+        let mut road_graph = RoadGraph { graph, node_map, edge_map: HashMap::new(), meta };
+        road_graph.rebuild_edge_map();
+        road_graph
+    }
+
+    // Must be called after edges are added or removed
+    pub fn rebuild_edge_map(&mut self) {
+        let mut edge_map: HashMap<u64, Vec<EdgeIndex>> = HashMap::new();
+        for e in self.graph.edge_indices() {
+            edge_map.entry(self.graph[e].source_id()).or_default().push(e);
+        }
+        let from = |e: &EdgeIndex| self.graph[*e].origin.map(|o| o.from).unwrap_or(0.0);
+        for chain in edge_map.values_mut() {
+            chain.sort_by(|a, b| from(a).total_cmp(&from(b)));
+        }
+        self.edge_map = edge_map;
+    }
+
+    // Edges that make up a source edge, in order along it. A single edge unless it was split.
+    pub fn source_edge_chain(&self, id: u64) -> &[EdgeIndex] {
+        self.edge_map.get(&id).map_or(&[], Vec::as_slice)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -62,6 +290,17 @@ pub struct Query {
     pub c: (f64, f64),
     pub d: f64,
     pub p: Profile,
+    pub end: Option<RouteEnd>,
+}
+
+// Where a route has to finish
+#[derive(Clone, Copy, Debug)]
+pub enum RouteEnd {
+    // Back within `radius` of the route's own start point, or of the query
+    // center when `at_center` is set
+    Loop { radius: f64, at_center: bool },
+    // Within `radius` of a fixed point, for point-to-point rides
+    Region { center: (f64, f64), radius: f64 },
 }
 
 #[derive(Clone, Debug)]
@@ -69,4 +308,21 @@ pub struct Route {
     pub si: f64,
     pub ti: f64,
     pub edge_ids: Vec<u64>,
-}
\ No newline at end of file
+}
+
+// A found route with how well it matches the query
+#[derive(Clone, Debug)]
+pub struct RouteMatch {
+    pub route: Route,
+    // Matcher score against the target profile; lower is better
+    pub score: f64,
+    // Score comparable across queries, e.g. area per meter of target
+    pub normalized_score: f64,
+    // Vertical shift applied to the route profile when scoring
+    pub offset: f64,
+    pub length: f64,
+    // Route length minus target length
+    pub length_error: f64,
+    // Route profile relative to its start, before the offset
+    pub profile: Profile,
+}
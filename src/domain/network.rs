@@ -0,0 +1,23 @@
+use petgraph::stable_graph::{EdgeIndex, NodeIndex};
+use std::borrow::Cow;
+
+use super::models::{EdgeData, GraphMeta, NodeData};
+
+// Read access the route search needs. Implemented by the in-memory graph with
+// its R-tree and by the memory-mapped CSR store, which builds values on demand.
+pub trait RoadNetwork {
+    fn meta(&self) -> &GraphMeta;
+    fn node_count(&self) -> usize;
+    fn edge_count(&self) -> usize;
+    fn node(&self, n: NodeIndex) -> NodeData;
+    fn edge(&self, e: EdgeIndex) -> Cow<'_, EdgeData>;
+    // Edges that carry a source edge id, in order along it
+    fn source_edges(&self, id: u64) -> Vec<EdgeIndex>;
+    fn edge_endpoints(&self, e: EdgeIndex) -> (NodeIndex, NodeIndex);
+    // Outgoing edges with their target, parallel edges included
+    fn out_edges(&self, n: NodeIndex) -> Vec<(EdgeIndex, NodeIndex)>;
+    // Incoming edges with their source
+    fn in_edges(&self, n: NodeIndex) -> Vec<(EdgeIndex, NodeIndex)>;
+    // Edges passing within `d` of `p`, with the fraction of the closest point along each
+    fn edges_near(&self, p: [f64; 2], d: f64) -> Vec<(EdgeIndex, f64)>;
+}
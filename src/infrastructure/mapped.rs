@@ -0,0 +1,479 @@
+use anyhow::{anyhow, Result};
+use hashbrown::HashMap;
+use memmap2::Mmap;
+use petgraph::stable_graph::{EdgeIndex, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::domain::{EdgeData, EdgeGeometry, EdgeOrigin, GraphMeta, NodeData, RoadGraph, RoadNetwork};
+use crate::infrastructure::{project_point_to_polyline, BuildInfo};
+
+// Memory-mapped layout: MAPPED_MAGIC, format version (u32 LE), header length
+// (u64 LE), the bincode `MappedHeader`, then flat little-endian arrays at the
+// payload offsets in `Sections`:
+//   nodes         x, y, elev (f64) per node
+//   out_offsets   CSR row pointers (u64, nodes + 1); out-edges of a node are contiguous
+//   edge_sources  source node (u32) per edge
+//   edge_targets  target node (u32) per edge
+//   in_offsets    reverse CSR row pointers (u64, nodes + 1) into in_edges
+//   in_edges      edge (u32) per entry, grouped by target node
+//   edges         id, flags, length, climb, slope, origin id/from/to (8 bytes each)
+//   geom_offsets  first polyline point (u64, edges + 1)
+//   geom_points   x, y, elev (f64, elev NaN when the polyline has none)
+//   source_index  source edge id (u64), edge (u32), padding (u32), sorted by id then
+//                 position along the source edge
+//   leaf_order    edge (u32) per packed R-tree leaf entry
+//   boxes         min x, min y, max x, max y (f64) per R-tree entry, leaves first
+// Nothing is decoded up front; values are read from the mapping on access.
+const MAPPED_MAGIC: [u8; 8] = *b"PPFCSR\0\0";
+pub const MAPPED_FORMAT_VERSION: u32 = 3;
+const RTREE_NODE_SIZE: usize = 16;
+
+const NODE_BYTES: usize = 24;
+const EDGE_BYTES: usize = 64;
+const POINT_BYTES: usize = 24;
+const BOX_BYTES: usize = 32;
+const SOURCE_ENTRY_BYTES: usize = 16;
+
+const HAS_ORIGIN: u64 = 1;
+const HAS_GEOMETRY: u64 = 2;
+const HAS_ELEVS: u64 = 4;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default)]
+struct Sections {
+    nodes: u64,
+    out_offsets: u64,
+    edge_sources: u64,
+    edge_targets: u64,
+    in_offsets: u64,
+    in_edges: u64,
+    edges: u64,
+    geom_offsets: u64,
+    geom_points: u64,
+    source_index: u64,
+    leaf_order: u64,
+    boxes: u64,
+    end: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct MappedHeader {
+    build: BuildInfo,
+    nodes: u64,
+    edges: u64,
+    geom_points: u64,
+    level_sizes: Vec<u64>,  // Packed R-tree entries per level, leaves first
+    sections: Sections,
+}
+
+pub fn is_mapped_file(path: &Path) -> Result<bool> {
+    let mut magic = [0u8; 8];
+    let mut file = File::open(path)?;
+    Ok(std::io::Read::read_exact(&mut file, &mut magic).is_ok() && magic == MAPPED_MAGIC)
+}
+
+fn edge_points(graph: &RoadGraph, e: EdgeIndex) -> Vec<[f64; 2]> {
+    match &graph.graph[e].geometry {
+        Some(geometry) => geometry.points.clone(),
+        None => {
+            let (u, v) = graph.graph.edge_endpoints(e).unwrap();
+            vec![[graph.graph[u].x, graph.graph[u].y], [graph.graph[v].x, graph.graph[v].y]]
+        }
+    }
+}
+
+fn bounding_box(points: &[[f64; 2]]) -> [f64; 4] {
+    points.iter().fold([f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY], |b, p| {
+        [b[0].min(p[0]), b[1].min(p[1]), b[2].max(p[0]), b[3].max(p[1])]
+    })
+}
+
+fn union(boxes: &[[f64; 4]]) -> [f64; 4] {
+    boxes.iter().fold([f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY], |b, c| {
+        [b[0].min(c[0]), b[1].min(c[1]), b[2].max(c[2]), b[3].max(c[3])]
+    })
+}
+
+// Sort-tile-recursive packing: leaves are tiles of nearby edges, and each upper
+// level groups RTREE_NODE_SIZE consecutive entries of the level below.
+fn pack_rtree(item_boxes: &[[f64; 4]]) -> (Vec<u32>, Vec<[f64; 4]>, Vec<u64>) {
+    let center = |b: &[f64; 4], axis: usize| (b[axis] + b[axis + 2]) / 2.0;
+    let mut order: Vec<u32> = (0..item_boxes.len() as u32).collect();
+    order.sort_by(|&a, &b| center(&item_boxes[a as usize], 0).total_cmp(&center(&item_boxes[b as usize], 0)));
+    let leaves = item_boxes.len().div_ceil(RTREE_NODE_SIZE);
+    let slices = (leaves as f64).sqrt().ceil().max(1.0) as usize;
+    for slice in order.chunks_mut(slices * RTREE_NODE_SIZE) {
+        slice.sort_by(|&a, &b| center(&item_boxes[a as usize], 1).total_cmp(&center(&item_boxes[b as usize], 1)));
+    }
+
+    let mut boxes: Vec<[f64; 4]> = order.iter().map(|&i| item_boxes[i as usize]).collect();
+    let mut level_sizes = Vec::new();
+    let mut level_start = 0;
+    let mut level_len = boxes.len();
+    while level_len > 0 {
+        level_sizes.push(level_len as u64);
+        if level_len == 1 {
+            break;
+        }
+        let parents: Vec<[f64; 4]> = boxes[level_start..level_start + level_len].chunks(RTREE_NODE_SIZE).map(union).collect();
+        level_start += level_len;
+        level_len = parents.len();
+        boxes.extend(parents);
+    }
+    (order, boxes, level_sizes)
+}
+
+pub fn save_mapped(graph: &RoadGraph, build: &BuildInfo, path: &Path) -> Result<()> {
+    let nodes: Vec<NodeIndex> = graph.graph.node_indices().collect();
+    let dense: HashMap<NodeIndex, u32> = nodes.iter().enumerate().map(|(i, &n)| (n, i as u32)).collect();
+    // Edges grouped by source, in the order the in-memory graph iterates them
+    let mut edges: Vec<EdgeIndex> = Vec::with_capacity(graph.graph.edge_count());
+    let mut out_offsets = vec![0u64];
+    for &n in &nodes {
+        edges.extend(graph.graph.edges(n).map(|e| e.id()));
+        out_offsets.push(edges.len() as u64);
+    }
+
+    let mut node_bytes = Vec::with_capacity(nodes.len() * NODE_BYTES);
+    for &n in &nodes {
+        let node = &graph.graph[n];
+        for value in [node.x, node.y, node.elev] {
+            node_bytes.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    let mut sources = Vec::with_capacity(edges.len() * 4);
+    let mut targets = Vec::with_capacity(edges.len() * 4);
+    let mut edge_bytes = Vec::with_capacity(edges.len() * EDGE_BYTES);
+    let mut geom_offsets = vec![0u64];
+    let mut geom_points = Vec::new();
+    let mut item_boxes = Vec::with_capacity(edges.len());
+    for &e in &edges {
+        let (u, v) = graph.graph.edge_endpoints(e).unwrap();
+        sources.extend_from_slice(&dense[&u].to_le_bytes());
+        targets.extend_from_slice(&dense[&v].to_le_bytes());
+        let edge = &graph.graph[e];
+        let mut flags = 0;
+        if edge.origin.is_some() {
+            flags |= HAS_ORIGIN;
+        }
+        if let Some(geometry) = &edge.geometry {
+            flags |= HAS_GEOMETRY;
+            if geometry.elevs.is_some() {
+                flags |= HAS_ELEVS;
+            }
+            for (i, p) in geometry.points.iter().enumerate() {
+                let elev = geometry.elevs.as_ref().map_or(f64::NAN, |elevs| elevs[i]);
+                for value in [p[0], p[1], elev] {
+                    geom_points.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+        geom_offsets.push((geom_points.len() / POINT_BYTES) as u64);
+        let origin = edge.origin.unwrap_or(EdgeOrigin { id: 0, from: 0.0, to: 1.0 });
+        edge_bytes.extend_from_slice(&edge.id.to_le_bytes());
+        edge_bytes.extend_from_slice(&flags.to_le_bytes());
+        for value in [edge.length, edge.climb, edge.slope] {
+            edge_bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        edge_bytes.extend_from_slice(&origin.id.to_le_bytes());
+        for value in [origin.from, origin.to] {
+            edge_bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        item_boxes.push(bounding_box(&edge_points(graph, e)));
+    }
+    let position: HashMap<EdgeIndex, u32> = edges.iter().enumerate().map(|(i, &e)| (e, i as u32)).collect();
+    let mut in_offsets = vec![0u64];
+    let mut in_bytes = Vec::with_capacity(edges.len() * 4);
+    for &n in &nodes {
+        for e in graph.graph.edges_directed(n, Direction::Incoming) {
+            in_bytes.extend_from_slice(&position[&e.id()].to_le_bytes());
+        }
+        in_offsets.push((in_bytes.len() / 4) as u64);
+    }
+    let mut source_ids: Vec<&u64> = graph.edge_map.keys().collect();
+    source_ids.sort();
+    let mut source_bytes = Vec::with_capacity(edges.len() * SOURCE_ENTRY_BYTES);
+    for id in source_ids {
+        for e in graph.source_edge_chain(*id) {
+            source_bytes.extend_from_slice(&id.to_le_bytes());
+            source_bytes.extend_from_slice(&position[e].to_le_bytes());
+            source_bytes.extend_from_slice(&0u32.to_le_bytes());
+        }
+    }
+    let (leaf_order, boxes, level_sizes) = pack_rtree(&item_boxes);
+    let leaf_bytes: Vec<u8> = leaf_order.iter().flat_map(|i| i.to_le_bytes()).collect();
+    let box_bytes: Vec<u8> = boxes.iter().flatten().flat_map(|v| v.to_le_bytes()).collect();
+    let offset_bytes = |offsets: &[u64]| -> Vec<u8> { offsets.iter().flat_map(|o| o.to_le_bytes()).collect() };
+
+    let arrays = [
+        node_bytes,
+        offset_bytes(&out_offsets),
+        sources,
+        targets,
+        offset_bytes(&in_offsets),
+        in_bytes,
+        edge_bytes,
+        offset_bytes(&geom_offsets),
+        geom_points,
+        source_bytes,
+        leaf_bytes,
+        box_bytes,
+    ];
+    let mut starts = Vec::with_capacity(arrays.len() + 1);
+    let mut pos = 0u64;
+    for array in &arrays {
+        starts.push(pos);
+        pos += array.len() as u64;
+    }
+    let sections = Sections {
+        nodes: starts[0],
+        out_offsets: starts[1],
+        edge_sources: starts[2],
+        edge_targets: starts[3],
+        in_offsets: starts[4],
+        in_edges: starts[5],
+        edges: starts[6],
+        geom_offsets: starts[7],
+        geom_points: starts[8],
+        source_index: starts[9],
+        leaf_order: starts[10],
+        boxes: starts[11],
+        end: pos,
+    };
+    let header = bincode::serialize(&MappedHeader {
+        build: build.clone(),
+        nodes: nodes.len() as u64,
+        edges: edges.len() as u64,
+        geom_points: *geom_offsets.last().unwrap(),
+        level_sizes,
+        sections,
+    })?;
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(&MAPPED_MAGIC)?;
+    writer.write_all(&MAPPED_FORMAT_VERSION.to_le_bytes())?;
+    writer.write_all(&(header.len() as u64).to_le_bytes())?;
+    writer.write_all(&header)?;
+    for array in &arrays {
+        writer.write_all(array)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub struct MappedGraph {
+    mmap: Mmap,
+    payload: usize,  // Byte offset of the arrays in the mapping
+    header: MappedHeader,
+}
+
+impl MappedGraph {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)?;
+        // Safety: the mapping is only read; as with any mmap, the file must not be
+        // truncated or rewritten while it is open
+        let mmap = unsafe { Mmap::map(&file)? };
+        if mmap.len() < 20 || mmap[..8] != MAPPED_MAGIC {
+            return Err(anyhow!("{:?} is not a memory-mapped graph file", path));
+        }
+        let version = u32::from_le_bytes(mmap[8..12].try_into().unwrap());
+        if version != MAPPED_FORMAT_VERSION {
+            return Err(anyhow!(
+                "{:?} uses mapped format version {}, but this build reads version {}; re-run preprocess",
+                path, version, MAPPED_FORMAT_VERSION
+            ));
+        }
+        let header_len = u64::from_le_bytes(mmap[12..20].try_into().unwrap()) as usize;
+        let payload = 20 + header_len;
+        if mmap.len() < payload {
+            return Err(anyhow!("{:?} is truncated: header is cut off", path));
+        }
+        let header: MappedHeader = bincode::deserialize(&mmap[20..payload])?;
+        let s = &header.sections;
+        let (n, m) = (header.nodes, header.edges);
+        let levels: u64 = header.level_sizes.iter().sum();
+        // Every array must have the length the counts imply, and fit in the file
+        let expected = [
+            (s.nodes, s.out_offsets, n * NODE_BYTES as u64),
+            (s.out_offsets, s.edge_sources, (n + 1) * 8),
+            (s.edge_sources, s.edge_targets, m * 4),
+            (s.edge_targets, s.in_offsets, m * 4),
+            (s.in_offsets, s.in_edges, (n + 1) * 8),
+            (s.in_edges, s.edges, m * 4),
+            (s.edges, s.geom_offsets, m * EDGE_BYTES as u64),
+            (s.geom_offsets, s.geom_points, (m + 1) * 8),
+            (s.geom_points, s.source_index, header.geom_points * POINT_BYTES as u64),
+            (s.source_index, s.leaf_order, m * SOURCE_ENTRY_BYTES as u64),
+            (s.leaf_order, s.boxes, m * 4),
+            (s.boxes, s.end, levels * BOX_BYTES as u64),
+        ];
+        if expected.iter().any(|&(start, end, len)| end.checked_sub(start) != Some(len))
+            || (mmap.len() - payload) as u64 != s.end
+        {
+            return Err(anyhow!("{:?} is truncated or damaged: section sizes do not match the header", path));
+        }
+        Ok(MappedGraph { mmap, payload, header })
+    }
+
+    pub fn build_info(&self) -> &BuildInfo {
+        &self.header.build
+    }
+
+    fn bytes<const N: usize>(&self, section: u64, offset: usize) -> [u8; N] {
+        let start = self.payload + section as usize + offset;
+        self.mmap[start..start + N].try_into().unwrap()
+    }
+
+    fn f64_at(&self, section: u64, offset: usize) -> f64 {
+        f64::from_le_bytes(self.bytes(section, offset))
+    }
+
+    fn u64_at(&self, section: u64, offset: usize) -> u64 {
+        u64::from_le_bytes(self.bytes(section, offset))
+    }
+
+    fn u32_at(&self, section: u64, offset: usize) -> u32 {
+        u32::from_le_bytes(self.bytes(section, offset))
+    }
+
+    fn geometry(&self, e: usize, with_elevs: bool) -> EdgeGeometry {
+        let s = &self.header.sections;
+        let first = self.u64_at(s.geom_offsets, e * 8) as usize;
+        let last = self.u64_at(s.geom_offsets, (e + 1) * 8) as usize;
+        let at = |i: usize, k: usize| self.f64_at(s.geom_points, i * POINT_BYTES + k * 8);
+        EdgeGeometry {
+            points: (first..last).map(|i| [at(i, 0), at(i, 1)]).collect(),
+            elevs: with_elevs.then(|| (first..last).map(|i| at(i, 2)).collect()),
+        }
+    }
+
+    fn edge_polyline(&self, e: usize) -> Vec<[f64; 2]> {
+        let flags = self.u64_at(self.header.sections.edges, e * EDGE_BYTES + 8);
+        if flags & HAS_GEOMETRY != 0 {
+            return self.geometry(e, false).points;
+        }
+        let (u, v) = self.edge_endpoints(EdgeIndex::new(e));
+        let (a, b) = (self.node(u), self.node(v));
+        vec![[a.x, a.y], [b.x, b.y]]
+    }
+
+    fn entry_box(&self, i: usize) -> [f64; 4] {
+        let at = |k: usize| self.f64_at(self.header.sections.boxes, i * BOX_BYTES + k * 8);
+        [at(0), at(1), at(2), at(3)]
+    }
+}
+
+impl RoadNetwork for MappedGraph {
+    fn meta(&self) -> &GraphMeta {
+        &self.header.build.meta
+    }
+
+    fn node_count(&self) -> usize {
+        self.header.nodes as usize
+    }
+
+    fn edge_count(&self) -> usize {
+        self.header.edges as usize
+    }
+
+    fn node(&self, n: NodeIndex) -> NodeData {
+        let at = |k: usize| self.f64_at(self.header.sections.nodes, n.index() * NODE_BYTES + k * 8);
+        NodeData { x: at(0), y: at(1), elev: at(2) }
+    }
+
+    fn edge(&self, e: EdgeIndex) -> Cow<'_, EdgeData> {
+        let base = e.index() * EDGE_BYTES;
+        let section = self.header.sections.edges;
+        let flags = self.u64_at(section, base + 8);
+        let origin = (flags & HAS_ORIGIN != 0).then(|| EdgeOrigin {
+            id: self.u64_at(section, base + 40),
+            from: self.f64_at(section, base + 48),
+            to: self.f64_at(section, base + 56),
+        });
+        Cow::Owned(EdgeData {
+            id: self.u64_at(section, base),
+            length: self.f64_at(section, base + 16),
+            climb: self.f64_at(section, base + 24),
+            slope: self.f64_at(section, base + 32),
+            origin,
+            geometry: (flags & HAS_GEOMETRY != 0).then(|| self.geometry(e.index(), flags & HAS_ELEVS != 0)),
+        })
+    }
+
+    fn source_edges(&self, id: u64) -> Vec<EdgeIndex> {
+        let section = self.header.sections.source_index;
+        let id_at = |i: usize| self.u64_at(section, i * SOURCE_ENTRY_BYTES);
+        // Binary search for the first entry with this id
+        let (mut lo, mut hi) = (0, self.header.edges as usize);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if id_at(mid) < id {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        (lo..self.header.edges as usize)
+            .take_while(|&i| id_at(i) == id)
+            .map(|i| EdgeIndex::new(self.u32_at(section, i * SOURCE_ENTRY_BYTES + 8) as usize))
+            .collect()
+    }
+
+    fn edge_endpoints(&self, e: EdgeIndex) -> (NodeIndex, NodeIndex) {
+        let s = &self.header.sections;
+        let u = self.u32_at(s.edge_sources, e.index() * 4);
+        let v = self.u32_at(s.edge_targets, e.index() * 4);
+        (NodeIndex::new(u as usize), NodeIndex::new(v as usize))
+    }
+
+    fn out_edges(&self, n: NodeIndex) -> Vec<(EdgeIndex, NodeIndex)> {
+        let s = &self.header.sections;
+        let first = self.u64_at(s.out_offsets, n.index() * 8) as usize;
+        let last = self.u64_at(s.out_offsets, (n.index() + 1) * 8) as usize;
+        (first..last)
+            .map(|e| (EdgeIndex::new(e), NodeIndex::new(self.u32_at(s.edge_targets, e * 4) as usize)))
+            .collect()
+    }
+
+    fn in_edges(&self, n: NodeIndex) -> Vec<(EdgeIndex, NodeIndex)> {
+        let s = &self.header.sections;
+        let first = self.u64_at(s.in_offsets, n.index() * 8) as usize;
+        let last = self.u64_at(s.in_offsets, (n.index() + 1) * 8) as usize;
+        (first..last)
+            .map(|i| {
+                let e = self.u32_at(s.in_edges, i * 4) as usize;
+                (EdgeIndex::new(e), NodeIndex::new(self.u32_at(s.edge_sources, e * 4) as usize))
+            })
+            .collect()
+    }
+
+    fn edges_near(&self, p: [f64; 2], d: f64) -> Vec<(EdgeIndex, f64)> {
+        let levels = &self.header.level_sizes;
+        let Some(top) = levels.len().checked_sub(1) else { return Vec::new() };
+        let level_start = |level: usize| levels[..level].iter().sum::<u64>() as usize;
+        let hits = |b: [f64; 4]| b[0] <= p[0] + d && b[2] >= p[0] - d && b[1] <= p[1] + d && b[3] >= p[1] - d;
+        let mut found = Vec::new();
+        let mut stack = vec![(top, 0usize)];
+        while let Some((level, i)) = stack.pop() {
+            if !hits(self.entry_box(level_start(level) + i)) {
+                continue;
+            }
+            if level == 0 {
+                let e = self.u32_at(self.header.sections.leaf_order, i * 4) as usize;
+                let (proj, fraction) = project_point_to_polyline(&p, &self.edge_polyline(e));
+                if ((proj[0] - p[0]).powi(2) + (proj[1] - p[1]).powi(2)).sqrt() <= d {
+                    found.push((EdgeIndex::new(e), fraction));
+                }
+                continue;
+            }
+            let children = levels[level - 1] as usize;
+            let first = i * RTREE_NODE_SIZE;
+            stack.extend((first..(first + RTREE_NODE_SIZE).min(children)).map(|c| (level - 1, c)));
+        }
+        found
+    }
+}
@@ -1,5 +1,7 @@
+mod mapped;
 mod persistence;
 mod spatial;
 
+pub use mapped::*;
 pub use persistence::*;
 pub use spatial::*;
\ No newline at end of file
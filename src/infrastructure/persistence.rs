@@ -1,12 +1,23 @@
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 use bincode;
-use rstar::RTree;
+use bincode::Options;
+use petgraph::stable_graph::{EdgeIndex, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use rstar::{RTree, AABB};
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::Path;
 
-use crate::domain::RoadGraph;
-use crate::infrastructure::SpatialEdge;
+use crate::domain::{EdgeData, GraphMeta, NodeData, RoadGraph, RoadNetwork};
+use crate::infrastructure::{distance_to_point, is_mapped_file, project_point_to_segment, MappedGraph, SpatialEdge};
+
+// File layout: MAGIC, format version (u32 LE), header length (u64 LE), the
+// bincode `FileHeader`, then the bincode `AppData` payload. Files without the
+// magic are the original headerless layout (version 0) and are migrated on load.
+const MAGIC: [u8; 8] = *b"PPFGRAPH";
+pub const FORMAT_VERSION: u32 = 2;
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct AppData {
@@ -14,16 +25,295 @@ pub struct AppData {
     pub rtree: RTree<SpatialEdge>,
 }
 
-pub fn save_data(data: &AppData, path: &Path) -> Result<()> {
+impl RoadNetwork for AppData {
+    fn meta(&self) -> &GraphMeta {
+        &self.graph.meta
+    }
+
+    fn node_count(&self) -> usize {
+        self.graph.graph.node_count()
+    }
+
+    fn edge_count(&self) -> usize {
+        self.graph.graph.edge_count()
+    }
+
+    fn node(&self, n: NodeIndex) -> NodeData {
+        self.graph.graph[n].clone()
+    }
+
+    fn edge(&self, e: EdgeIndex) -> Cow<'_, EdgeData> {
+        Cow::Borrowed(&self.graph.graph[e])
+    }
+
+    fn source_edges(&self, id: u64) -> Vec<EdgeIndex> {
+        self.graph.source_edge_chain(id).to_vec()
+    }
+
+    fn edge_endpoints(&self, e: EdgeIndex) -> (NodeIndex, NodeIndex) {
+        self.graph.graph.edge_endpoints(e).unwrap()
+    }
+
+    fn out_edges(&self, n: NodeIndex) -> Vec<(EdgeIndex, NodeIndex)> {
+        self.graph.graph.edges(n).map(|e| (e.id(), e.target())).collect()
+    }
+
+    fn in_edges(&self, n: NodeIndex) -> Vec<(EdgeIndex, NodeIndex)> {
+        self.graph.graph.edges_directed(n, Direction::Incoming).map(|e| (e.id(), e.source())).collect()
+    }
+
+    fn edges_near(&self, p: [f64; 2], d: f64) -> Vec<(EdgeIndex, f64)> {
+        let bound_box = AABB::from_corners([p[0] - d, p[1] - d], [p[0] + d, p[1] + d]);
+        self.rtree
+            .locate_in_envelope_intersecting(&bound_box)
+            .filter(|se| distance_to_point(&p, se) <= d)
+            .map(|se| (se.e_idx, project_point_to_segment(&p, se).1))
+            .collect()
+    }
+}
+
+// Describes how a `.bin` file was built; stored in the file header
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct BuildInfo {
+    pub tool_version: String,
+    pub source: Option<String>,
+    pub source_crc32: Option<u32>,
+    pub meta: GraphMeta,
+    pub nodes: u64,
+    pub edges: u64,
+}
+
+impl BuildInfo {
+    pub fn new(graph: &RoadGraph, source: Option<&Path>) -> Result<Self> {
+        let source_crc32 = match source {
+            Some(path) => Some(file_crc32(path).with_context(|| format!("hashing {:?}", path))?),
+            None => None,
+        };
+        Ok(BuildInfo {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            source: source.map(|p| p.display().to_string()),
+            source_crc32,
+            meta: graph.meta.clone(),
+            nodes: graph.graph.node_count() as u64,
+            edges: graph.graph.edge_count() as u64,
+        })
+    }
+}
+
+impl std::fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} nodes, {} edges, built by v{}", self.nodes, self.edges, self.tool_version)?;
+        if let Some(source) = &self.source {
+            write!(f, " from {}", source)?;
+        }
+        if let Some(crc) = self.source_crc32 {
+            write!(f, " (crc32 {:08x})", crc)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct FileHeader {
+    build: BuildInfo,
+    payload_len: u64,
+    payload_crc32: u32,
+}
+
+fn file_crc32(path: &Path) -> Result<u32> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize())
+}
+
+pub fn save_data(data: &AppData, build: &BuildInfo, path: &Path) -> Result<()> {
+    let payload = bincode::serialize(data)?;
+    let header = bincode::serialize(&FileHeader {
+        build: build.clone(),
+        payload_len: payload.len() as u64,
+        payload_crc32: crc32fast::hash(&payload),
+    })?;
     let file = File::create(path)?;
     let mut writer = BufWriter::new(file);
-    bincode::serialize_into(&mut writer, data)?;
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    writer.write_all(&(header.len() as u64).to_le_bytes())?;
+    writer.write_all(&header)?;
+    writer.write_all(&payload)?;
+    writer.flush()?;
     Ok(())
 }
 
 pub fn load_data(path: &Path) -> Result<AppData> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let data: AppData = bincode::deserialize_from(reader)?;
-    Ok(data)
-}
\ No newline at end of file
+    load_data_with_info(path).map(|(data, _)| data)
+}
+
+pub fn load_data_with_info(path: &Path) -> Result<(AppData, Option<BuildInfo>)> {
+    if is_mapped_file(path)? {
+        return Err(anyhow!("{:?} is a memory-mapped graph; write it with --output-format bincode to use it here", path));
+    }
+    let mut bytes = Vec::new();
+    File::open(path)?.read_to_end(&mut bytes)?;
+    let Some(rest) = bytes.strip_prefix(&MAGIC) else {
+        let data = legacy::load_v0(&bytes).map_err(|e| {
+            anyhow!("{:?} is not a graph file written by preprocess (no header, and not a headerless version 0 file: {})", path, e)
+        })?;
+        return Ok((data, None));
+    };
+    let (version, rest) = split_u32(rest).ok_or_else(|| anyhow!("{:?} is truncated: missing format version", path))?;
+    if version > FORMAT_VERSION {
+        return Err(anyhow!(
+            "{:?} uses format version {}, but this build reads up to version {}; update the tools or re-run preprocess",
+            path, version, FORMAT_VERSION
+        ));
+    }
+    let (header, payload) = read_header(rest).with_context(|| format!("{:?} has a damaged header", path))?;
+    if payload.len() as u64 != header.payload_len {
+        return Err(anyhow!("{:?} is truncated: payload is {} bytes, header says {}", path, payload.len(), header.payload_len));
+    }
+    let crc = crc32fast::hash(payload);
+    if crc != header.payload_crc32 {
+        return Err(anyhow!("{:?} is corrupt: payload crc32 {:08x} does not match header {:08x}", path, crc, header.payload_crc32));
+    }
+    let decode_error = |e| anyhow!("failed to decode {:?} (format version {}): {}; re-run preprocess", path, version, e);
+    let data = match version {
+        FORMAT_VERSION => bincode::deserialize(payload).map_err(decode_error)?,
+        1 => legacy::load_v1(payload).map_err(decode_error)?,
+        _ => return Err(anyhow!("{:?} uses format version {}, which has no migration; re-run preprocess", path, version)),
+    };
+    Ok((data, Some(header.build)))
+}
+
+// Open either layout for searching: bincode files are loaded into memory,
+// memory-mapped files are queried in place.
+pub fn load_network(path: &Path) -> Result<(Box<dyn RoadNetwork>, Option<BuildInfo>)> {
+    if is_mapped_file(path)? {
+        let graph = MappedGraph::open(path)?;
+        let build = graph.build_info().clone();
+        return Ok((Box::new(graph), Some(build)));
+    }
+    let (data, build) = load_data_with_info(path)?;
+    Ok((Box::new(data), build))
+}
+
+fn split_u32(bytes: &[u8]) -> Option<(u32, &[u8])> {
+    let (head, rest) = bytes.split_first_chunk::<4>()?;
+    Some((u32::from_le_bytes(*head), rest))
+}
+
+fn read_header(bytes: &[u8]) -> Result<(FileHeader, &[u8])> {
+    let (len, rest) = bytes.split_first_chunk::<8>().ok_or_else(|| anyhow!("missing header length"))?;
+    let len = u64::from_le_bytes(*len) as usize;
+    if rest.len() < len {
+        return Err(anyhow!("header is {} bytes but only {} remain", len, rest.len()));
+    }
+    let (header, payload) = rest.split_at(len);
+    Ok((bincode::deserialize(header)?, payload))
+}
+
+// Earlier layouts. Version 0 is the original headerless raw bincode of the graph
+// and R-tree, before edges had origins or geometry and before the graph carried
+// its meta record; version 1 had the header but no edge-id index.
+mod legacy {
+    use super::*;
+    use crate::domain::{EdgeData, NodeData};
+    use hashbrown::HashMap;
+    use petgraph::stable_graph::{EdgeIndex, NodeIndex, StableGraph};
+    use rstar::{RTreeObject, AABB};
+
+    #[derive(serde::Deserialize)]
+    struct EdgeDataV0 {
+        id: u64,
+        length: f64,
+        climb: f64,
+        slope: f64,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct RoadGraphV0 {
+        graph: StableGraph<NodeData, EdgeDataV0>,
+        node_map: HashMap<u64, NodeIndex>,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct RoadGraphV1 {
+        graph: StableGraph<NodeData, EdgeData>,
+        node_map: HashMap<u64, NodeIndex>,
+        meta: GraphMeta,
+    }
+
+    #[derive(serde::Deserialize)]
+    struct AppDataV1 {
+        graph: RoadGraphV1,
+        rtree: RTree<SpatialEdge>,
+    }
+
+    pub(super) fn load_v1(payload: &[u8]) -> bincode::Result<AppData> {
+        let old: AppDataV1 = bincode::deserialize(payload)?;
+        Ok(AppData { graph: RoadGraph::new(old.graph.graph, old.graph.node_map, old.graph.meta), rtree: old.rtree })
+    }
+
+    #[derive(serde::Deserialize)]
+    struct SpatialEdgeV0 {
+        p_u: [f64; 2],
+        p_v: [f64; 2],
+        u: NodeIndex,
+        v: NodeIndex,
+        e_idx: EdgeIndex,
+        length: f64,
+        climb: f64,
+        slope: f64,
+        id: u64,
+    }
+
+    impl RTreeObject for SpatialEdgeV0 {
+        type Envelope = AABB<[f64; 2]>;
+
+        fn envelope(&self) -> Self::Envelope {
+            AABB::from_points(&[self.p_u, self.p_v])
+        }
+    }
+
+    #[derive(serde::Deserialize)]
+    struct AppDataV0 {
+        graph: RoadGraphV0,
+        rtree: RTree<SpatialEdgeV0>,
+    }
+
+    pub(super) fn load_v0(bytes: &[u8]) -> Result<AppData> {
+        // Unlike `bincode::deserialize`, reject trailing bytes so other layouts are not misread
+        let old: AppDataV0 = bincode::options().with_fixint_encoding().reject_trailing_bytes().deserialize(bytes)?;
+        let graph = old.graph.graph.map(
+            |_, n| n.clone(),
+            |_, e| EdgeData { id: e.id, length: e.length, climb: e.climb, slope: e.slope, origin: None, geometry: None },
+        );
+        let spatial_edges = old
+            .rtree
+            .iter()
+            .map(|se| SpatialEdge {
+                p_u: se.p_u,
+                p_v: se.p_v,
+                u: se.u,
+                v: se.v,
+                e_idx: se.e_idx,
+                length: se.length,
+                climb: se.climb,
+                slope: se.slope,
+                id: se.id,
+                geometry: None,
+            })
+            .collect();
+        Ok(AppData {
+            graph: RoadGraph::new(graph, old.graph.node_map, GraphMeta::default()),
+            rtree: RTree::bulk_load(spatial_edges),
+        })
+    }
+}
@@ -1,4 +1,5 @@
 use rstar::{AABB, PointDistance, RTreeObject};
+use std::borrow::Cow;
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct SpatialEdge {
@@ -11,13 +12,23 @@ pub struct SpatialEdge {
     pub climb: f64,
     pub slope: f64,
     pub id: u64,
+    pub geometry: Option<Vec<[f64; 2]>>,  // Full polyline p_u .. p_v for curved edges
+}
+
+impl SpatialEdge {
+    pub fn points(&self) -> Cow<'_, [[f64; 2]]> {
+        match &self.geometry {
+            Some(points) => Cow::Borrowed(points),
+            None => Cow::Owned(vec![self.p_u, self.p_v]),
+        }
+    }
 }
 
 impl RTreeObject for SpatialEdge {
     type Envelope = AABB<[f64; 2]>;
 
     fn envelope(&self) -> Self::Envelope {
-        AABB::from_points(&[self.p_u, self.p_v])
+        AABB::from_points(self.points().iter())
     }
 }
 
@@ -29,23 +40,75 @@ impl PointDistance for SpatialEdge {
 
 pub fn distance_to_point(point: &[f64; 2], se: &SpatialEdge) -> f64 {
     let (proj, _) = project_point_to_segment(point, se);
-    (proj[0] - point[0]).powi(2) + (proj[1] - point[1]).powi(2).sqrt()
+    ((proj[0] - point[0]).powi(2) + (proj[1] - point[1]).powi(2)).sqrt()
 }
 
 pub fn project_point_to_segment(point: &[f64; 2], se: &SpatialEdge) -> ([f64; 2], f64) {
-    let a = point[0] - se.p_u[0];
-    let b = point[1] - se.p_u[1];
-    let c = se.p_v[0] - se.p_u[0];
-    let d = se.p_v[1] - se.p_u[1];
+    project_point_to_polyline(point, &se.points())
+}
+
+// Project onto the nearest sub-segment of a polyline. The fraction is by planar
+// arc length along the whole polyline.
+pub fn project_point_to_polyline(point: &[f64; 2], points: &[[f64; 2]]) -> ([f64; 2], f64) {
+    let mut best = (f64::INFINITY, points[0], 0.0);
+    let mut s = 0.0;
+    for w in points.windows(2) {
+        let (proj, param) = project_point_to_line(point, &w[0], &w[1]);
+        let seg_len = ((w[1][0] - w[0][0]).powi(2) + (w[1][1] - w[0][1]).powi(2)).sqrt();
+        let dist_sq = (proj[0] - point[0]).powi(2) + (proj[1] - point[1]).powi(2);
+        if dist_sq < best.0 {
+            best = (dist_sq, proj, s + param * seg_len);
+        }
+        s += seg_len;
+    }
+    let fraction = if s > 0.0 { best.2 / s } else { 0.0 };
+    (best.1, fraction.clamp(0.0, 1.0))
+}
+
+// Fractions (by planar arc length) of the first and last points of a polyline
+// within `d` of `point`, or None when no part of it is that close
+pub fn polyline_range_within(point: &[f64; 2], points: &[[f64; 2]], d: f64) -> Option<(f64, f64)> {
+    let mut range: Option<(f64, f64)> = None;
+    let mut s = 0.0;
+    for w in points.windows(2) {
+        let (dx, dy) = (w[1][0] - w[0][0], w[1][1] - w[0][1]);
+        let (fx, fy) = (w[0][0] - point[0], w[0][1] - point[1]);
+        let seg_len = (dx * dx + dy * dy).sqrt();
+        // |w0 + u (w1 - w0) - point|^2 <= d^2, solved for u in [0, 1]
+        let a = dx * dx + dy * dy;
+        let b = 2.0 * (fx * dx + fy * dy);
+        let c = fx * fx + fy * fy - d * d;
+        let hit = if a == 0.0 {
+            (c <= 0.0).then_some((0.0, 0.0))
+        } else {
+            let disc = b * b - 4.0 * a * c;
+            let (u0, u1) = ((-b - disc.sqrt()) / (2.0 * a), (-b + disc.sqrt()) / (2.0 * a));
+            (disc >= 0.0 && u1 >= 0.0 && u0 <= 1.0).then(|| (u0.max(0.0), u1.min(1.0)))
+        };
+        if let Some((u0, u1)) = hit {
+            let (lo, hi) = (s + u0 * seg_len, s + u1 * seg_len);
+            range = Some(range.map_or((lo, hi), |(a, b)| (a.min(lo), b.max(hi))));
+        }
+        s += seg_len;
+    }
+    let total = s;
+    range.map(|(lo, hi)| if total > 0.0 { (lo / total, hi / total) } else { (0.0, 0.0) })
+}
+
+fn project_point_to_line(point: &[f64; 2], p_u: &[f64; 2], p_v: &[f64; 2]) -> ([f64; 2], f64) {
+    let a = point[0] - p_u[0];
+    let b = point[1] - p_u[1];
+    let c = p_v[0] - p_u[0];
+    let d = p_v[1] - p_u[1];
     let dot = a * c + b * d;
     let len_sq = c * c + d * d;
     let param = if len_sq != 0.0 { dot / len_sq } else { -1.0 };
     let (xx, yy) = if param < 0.0 {
-        (se.p_u[0], se.p_u[1])
+        (p_u[0], p_u[1])
     } else if param > 1.0 {
-        (se.p_v[0], se.p_v[1])
+        (p_v[0], p_v[1])
     } else {
-        (se.p_u[0] + param * c, se.p_u[1] + param * d)
+        (p_u[0] + param * c, p_u[1] + param * d)
     };
-    ([xx, yy], param.max(0.0).min(1.0))
-}
\ No newline at end of file
+    ([xx, yy], param.clamp(0.0, 1.0))
+}